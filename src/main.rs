@@ -23,327 +23,7492 @@
 // core-graphics = "0.23"
 
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const HZ_LIST: usize = 64;
 
-struct DeviceStats {
-    name: String,
-    hz_history: VecDeque<u32>,
-    avg_hz: u32,
-    prev_time: Option<Instant>,
+/// Rates at or above this are treated as glitches rather than samples.
+const MAX_HZ: u64 = 20_000;
+
+/// How far past its declared USB rate a device may average before the
+/// exit report warns about it, allowing for jitter.
+const OVER_DECLARED: f64 = 1.1;
+
+/// Span counted by `--windowed` live rates.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Upper bounds in microseconds of the `--gaps` buckets; the last bucket
+/// takes everything above.
+const GAP_BUCKETS: [u64; 3] = [200, 500, 1000];
+const GAP_LABELS: [&str; 4] = ["<200µs", "200-500µs", "500µs-1ms", ">1ms"];
+
+/// Exit code for failures without a more specific code.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code for an unknown option or an invalid option value.
+const EXIT_USAGE: i32 = 2;
+/// Exit code when no device is left to monitor.
+const EXIT_NO_DEVICES: i32 = 3;
+/// Exit code when input devices exist but can't be opened.
+#[cfg(target_os = "linux")]
+const EXIT_PERMISSION: i32 = 4;
+/// Exit code when the macOS event tap can't be created.
+#[cfg(target_os = "macos")]
+const EXIT_NO_EVENT_TAP: i32 = 5;
+/// Exit code when `--probe` saw no events to average.
+const EXIT_NO_EVENTS: i32 = 6;
+/// Exit code when an `--assert` band isn't met.
+const EXIT_ASSERT_FAILED: i32 = 7;
+
+/// How often `--focus-window` checks which window is focused.
+#[cfg(target_os = "linux")]
+const FOCUS_POLL: Duration = Duration::from_millis(100);
+
+/// How long `--probe` samples before printing its one number, unless
+/// `--duration` says otherwise.
+const PROBE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Why a measurement run couldn't start.
+#[derive(Debug)]
+enum EvhzError {
+    /// Nothing was left to monitor after scanning and filtering.
+    #[cfg(target_os = "linux")]
+    NoDevices(String),
+    /// A display named a device that isn't there.
+    NotFound(String),
+    /// Devices exist under this directory but can't be opened.
+    #[cfg(target_os = "linux")]
+    PermissionDenied(&'static str),
+    /// The macOS event tap, or its run loop source, couldn't be created.
+    #[cfg(target_os = "macos")]
+    TapCreationFailed(&'static str),
+    /// Registering for Windows raw input failed.
+    #[cfg(target_os = "windows")]
+    RawInput(windows::core::Error),
+    Io(&'static str, std::io::Error),
 }
 
-impl DeviceStats {
-    fn new(name: String) -> Self {
-        Self {
-            name,
-            hz_history: VecDeque::with_capacity(HZ_LIST),
-            avg_hz: 0,
-            prev_time: None,
+impl EvhzError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            EvhzError::NotFound(_) => EXIT_NO_DEVICES,
+            #[cfg(target_os = "linux")]
+            EvhzError::NoDevices(_) => EXIT_NO_DEVICES,
+            #[cfg(target_os = "linux")]
+            EvhzError::PermissionDenied(_) => EXIT_PERMISSION,
+            #[cfg(target_os = "macos")]
+            EvhzError::TapCreationFailed(_) => EXIT_NO_EVENT_TAP,
+            #[cfg(target_os = "windows")]
+            EvhzError::RawInput(_) => EXIT_FAILURE,
+            EvhzError::Io(..) => EXIT_FAILURE,
         }
     }
 
-    fn update(&mut self, verbose: bool) {
-        let time = Instant::now();
+    /// Whether there is simply no input to measure here, which
+    /// `--allow-no-devices` accepts.
+    fn unavailable(&self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            EvhzError::NoDevices(_) | EvhzError::PermissionDenied(_) => true,
+            #[cfg(target_os = "macos")]
+            EvhzError::TapCreationFailed(_) => true,
+            _ => false,
+        }
+    }
 
-        if let Some(prev) = self.prev_time {
-            let diff = time.duration_since(prev);
-            let micros = diff.as_micros() as u64;
+    /// Reports the error and exits with its code, or with 0 under
+    /// `--allow-no-devices` when there is no input to measure.
+    fn exit(&self, capture: &CaptureConfig) -> ! {
+        let message = self.to_string();
+        if !to_syslog(Severity::Error, &message) {
+            eprintln!("{}", message);
+        }
+        let tolerated = capture.allow_no_devices && self.unavailable();
+        std::process::exit(if tolerated { 0 } else { self.exit_code() });
+    }
+}
 
-            if micros > 0 {
-                let hz = (1_000_000u64 / micros) as u32;
+impl std::fmt::Display for EvhzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EvhzError::NotFound(message) => write!(f, "{}", message),
+            #[cfg(target_os = "linux")]
+            EvhzError::NoDevices(message) => write!(f, "{}", message),
+            // hidraw nodes, which udev leaves root-only by default
+            #[cfg(target_os = "linux")]
+            EvhzError::PermissionDenied("/dev") => {
+                writeln!(f, "Cannot access /dev/hidraw* devices.")?;
+                writeln!(f, "hidraw nodes are root-only unless a udev rule opens them up, e.g. in")?;
+                writeln!(f, "/etc/udev/rules.d/70-hidraw.rules:")?;
+                writeln!(f, "  KERNEL==\"hidraw*\", SUBSYSTEM==\"hidraw\", MODE=\"0660\", GROUP=\"input\"")?;
+                writeln!(f, "Then run: sudo udevadm control --reload && sudo udevadm trigger")?;
+                write!(f, "and add your user to the 'input' group, or run with sudo.")
+            }
+            #[cfg(target_os = "linux")]
+            EvhzError::PermissionDenied(dir) => {
+                writeln!(f, "Cannot access {} devices.", dir)?;
+                writeln!(f, "To run without root, add your user to the 'input' group:")?;
+                writeln!(f, "  sudo usermod -aG input $USER")?;
+                write!(f, "Then log out and log back in, or run with sudo.")
+            }
+            #[cfg(target_os = "macos")]
+            EvhzError::TapCreationFailed(what) => {
+                writeln!(f, "Failed to create {}.", what)?;
+                writeln!(f, "evhz needs a logged-in GUI session (not SSH or a headless runner) and")?;
+                write!(f, "Input Monitoring permission for this terminal, or run it with sudo.")
+            }
+            #[cfg(target_os = "windows")]
+            EvhzError::RawInput(err) => write!(f, "Failed to register for raw input: {}", err),
+            EvhzError::Io(context, err) => write!(f, "{}: {}", context, err),
+        }
+    }
+}
 
-                if hz > 0 && hz < 20000 {
-                    if self.hz_history.len() >= HZ_LIST {
-                        self.hz_history.pop_front();
-                    }
-                    self.hz_history.push_back(hz);
+/// An `--assert NAME=HZ±PCT%` band a device's exit average must fall in.
+#[derive(Clone, Debug, PartialEq)]
+struct RateAssertion {
+    pattern: String,
+    hz: f64,
+    tolerance_pct: f64,
+}
 
-                    let sum: u32 = self.hz_history.iter().sum();
-                    self.avg_hz = sum / self.hz_history.len() as u32;
+impl RateAssertion {
+    /// Parses `NAME=HZ±PCT%`, also taking `+-` for `±` and leaving out `%`.
+    fn parse(value: &str) -> Option<Self> {
+        let (pattern, band) = value.rsplit_once('=')?;
+        let (hz, tolerance) = band.split_once('±').or_else(|| band.split_once("+-"))?;
+        let hz: f64 = hz.trim().parse().ok()?;
+        let tolerance_pct: f64 = tolerance.trim().trim_end_matches('%').parse().ok()?;
+        let valid = !pattern.trim().is_empty() && hz > 0.0 && tolerance_pct >= 0.0;
+        valid.then(|| RateAssertion {
+            pattern: pattern.trim().to_string(),
+            hz,
+            tolerance_pct,
+        })
+    }
 
-                    if verbose {
-                        println!(
-                            "{}: Latest {:5}Hz, Average {:5}Hz",
-                            self.name, hz, self.avg_hz
-                        );
-                    }
-                }
+    /// Checks the band against the device named exactly like the pattern,
+    /// or else the first whose name contains it, printing PASS or FAIL.
+    fn check(&self, output: &OutputConfig, devices: &[DeviceSummary]) -> bool {
+        let pattern = self.pattern.to_lowercase();
+        let device = devices
+            .iter()
+            .find(|device| device.name.to_lowercase() == pattern)
+            .or_else(|| devices.iter().find(|device| device.name.to_lowercase().contains(&pattern)));
+        let unit = output.unit.suffix();
+        let band = format!(
+            "{}{} ±{}%",
+            output.format_hz(self.hz),
+            unit,
+            self.tolerance_pct
+        );
+        let (passed, line) = match device.filter(|device| device.avg_hz > 0.0) {
+            Some(device) => {
+                let passed = (device.avg_hz - self.hz).abs() <= self.hz * self.tolerance_pct / 100.0;
+                let verdict = if passed { "PASS" } else { "FAIL" };
+                let line = format!(
+                    "{} {}: {}{}, expected {}",
+                    verdict,
+                    device.name,
+                    output.format_hz(device.avg_hz),
+                    unit,
+                    band
+                );
+                (passed, line)
             }
+            None => (false, format!("FAIL {}: not measured, expected {}", self.pattern, band)),
+        };
+        // JSON keeps stdout to the one document
+        if output.summary == SummaryFormat::Text {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
         }
+        passed
+    }
+}
 
-        self.prev_time = Some(time);
+/// Per-device averages cached between runs for `--compare-previous`, one
+/// `name<TAB>avg_hz` line each.
+struct PreviousRun(Vec<(String, f64)>);
+
+impl PreviousRun {
+    /// `$XDG_CACHE_HOME/evhz/last-run.tsv`, falling back to `~/.cache` or,
+    /// on Windows, `%LOCALAPPDATA%`.
+    fn path() -> Option<std::path::PathBuf> {
+        let dir = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(std::path::PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".cache")))
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from))?;
+        Some(dir.join("evhz").join("last-run.tsv"))
     }
 
-    fn print_average(&self) {
-        if self.avg_hz > 0 {
-            println!("Average for {}: {:5}Hz", self.name, self.avg_hz);
+    /// The cached run, or an empty one on the first run.
+    fn load(path: &std::path::Path) -> Self {
+        std::fs::read_to_string(path).map_or(PreviousRun(Vec::new()), |text| Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter_map(|line| line.rsplit_once('\t'))
+            .filter_map(|(name, hz)| Some((name.to_string(), hz.parse().ok()?)))
+            .collect();
+        PreviousRun(entries)
+    }
+
+    fn render(&self) -> String {
+        self.0.iter().map(|(name, hz)| format!("{}\t{:.3}\n", name, hz)).collect()
+    }
+
+    fn save(&self, path: &std::path::Path) {
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(path, self.render()));
+        if let Err(err) = written {
+            eprintln!("Failed to write {}: {}", path.display(), err);
         }
     }
+
+    fn get(&self, name: &str) -> Option<f64> {
+        self.0.iter().find(|(saved, _)| saved == name).map(|&(_, hz)| hz)
+    }
 }
 
-#[cfg(target_os = "linux")]
-mod platform {
-    use super::*;
-    use evdev::{Device, InputEventKind};
-    use std::fs;
+/// What a measurement run leaves for the final report.
+struct RunSummary {
+    devices: Vec<DeviceSummary>,
+    /// Time from the first scan to the end of the run.
+    duration: Duration,
+    /// Hold times by device label, with `--click-timing`.
+    #[cfg(target_os = "linux")]
+    clicks: Vec<(String, ClickTiming)>,
+    /// Each device's evdev properties, with `--debug-devices`.
+    #[cfg(target_os = "linux")]
+    device_info: Vec<String>,
+    /// Averages from the last run, with `--compare-previous`.
+    previous: Option<PreviousRun>,
+    /// The runs before this one, with `--runs`.
+    earlier: Vec<RunSummary>,
+}
 
-    pub fn run(verbose: bool, running: Arc<AtomicBool>) {
-        let mut devices = Vec::new();
-        let mut stats_map = std::collections::HashMap::new();
+/// One device's session-wide numbers, next to the stats the detailed report
+/// reads.
+struct DeviceSummary {
+    name: String,
+    avg_hz: f64,
+    min_hz: u32,
+    max_hz: u32,
+    /// Samples that passed the Hz filter over the whole run.
+    count: u64,
+    stddev_hz: f64,
+    stats: DeviceStats,
+}
 
-        // Scan /dev/input/event* devices
-        for entry in fs::read_dir("/dev/input").expect("Failed to read /dev/input") {
-            let entry = entry.unwrap();
-            let path = entry.path();
+impl DeviceSummary {
+    /// Takes the final stats, re-averaging them as `--trim` asks.
+    fn new(mut stats: DeviceStats, output: &OutputConfig) -> Self {
+        if stats.avg_hz > 0 {
+            stats.avg_hz_exact = output.trim.average(&stats);
+            stats.avg_hz = output.rounding.apply(stats.avg_hz_exact) as u32;
+        }
+        let measured = stats.count > 0;
+        Self {
+            name: stats.name.clone(),
+            avg_hz: stats.avg_hz_exact,
+            min_hz: if measured { stats.min_hz } else { 0 },
+            max_hz: stats.max_hz,
+            count: stats.count,
+            stddev_hz: if measured { (stats.m2_hz / stats.count as f64).sqrt() } else { 0.0 },
+            stats,
+        }
+    }
+
+    fn to_json(&self, output: &OutputConfig, previous: Option<&PreviousRun>) -> Json {
+        let mut fields = vec![("name", Json::String(self.name.clone()))];
+        fields.extend(self.stats.dpi.map(|dpi| ("dpi", Json::Number(dpi.to_string()))));
+        fields.extend([
+            ("avg_hz", Json::hz(output, self.avg_hz)),
+            ("samples", Json::Number(self.stats.hz_history.len().to_string())),
+            ("count", Json::Number(self.count.to_string())),
+            ("min_hz", Json::hz(output, self.min_hz as f64)),
+            ("max_hz", Json::hz(output, self.max_hz as f64)),
+            ("stddev_hz", Json::hz(output, self.stddev_hz)),
+            ("jitter_us", self.stats.jitter_us().map_or(Json::Null, |us| Json::Number(format!("{:.1}", us)))),
+        ]);
+        fields.extend(self.stats.json_fields(output));
+        if let Some(previous) = previous {
+            let hz = previous.get(&self.name);
+            fields.push(("previous_avg_hz", hz.map_or(Json::Null, |hz| Json::hz(output, hz))));
+        }
+        Json::Object(fields)
+    }
+}
 
-            if let Some(name) = path.file_name() {
-                let name_str = name.to_string_lossy();
-                if name_str.starts_with("event") {
-                    if let Ok(device) = Device::open(&path) {
-                        let dev_name = device.name().unwrap_or("Unknown").to_string();
+impl RunSummary {
+    fn new(devices: Vec<DeviceStats>, started: Instant, output: &OutputConfig) -> Self {
+        Self {
+            devices: devices.into_iter().map(|stats| DeviceSummary::new(stats, output)).collect(),
+            duration: started.elapsed(),
+            #[cfg(target_os = "linux")]
+            clicks: Vec::new(),
+            #[cfg(target_os = "linux")]
+            device_info: Vec::new(),
+            previous: None,
+            earlier: Vec::new(),
+        }
+    }
 
-                        if verbose {
-                            println!("{}: {}", name_str, dev_name);
-                        }
+    /// Every run of the session in order, this one last.
+    fn runs(&self) -> Vec<&RunSummary> {
+        self.earlier.iter().chain(std::iter::once(self)).collect()
+    }
 
-                        stats_map.insert(path.clone(), DeviceStats::new(dev_name));
-                        devices.push((path.clone(), device));
-                    }
+    /// The `--probe` figure: the whole-Hz average of the device that sent
+    /// the most samples, the one being moved when several are monitored.
+    fn probe_hz(&self) -> Option<u32> {
+        let device = self.devices.iter().filter(|device| device.avg_hz > 0.0).max_by_key(|device| device.count)?;
+        Some(device.stats.avg_hz)
+    }
+
+    fn previous_hz(&self, device: &DeviceSummary) -> Option<f64> {
+        self.previous.as_ref()?.get(&device.name)
+    }
+
+    /// The cache entries after this run: every measured device's average,
+    /// plus the entries of devices this run didn't see.
+    fn next_run(&self) -> PreviousRun {
+        let mut entries: Vec<(String, f64)> = self
+            .devices
+            .iter()
+            .filter(|device| device.avg_hz > 0.0)
+            .map(|device| (device.name.clone(), device.avg_hz))
+            .collect();
+        if let Some(previous) = &self.previous {
+            for (name, hz) in &previous.0 {
+                if !entries.iter().any(|(seen, _)| seen == name) {
+                    entries.push((name.clone(), *hz));
                 }
             }
         }
+        PreviousRun(entries)
+    }
+
+    /// The `--columns` table: a header row, then a row per measured device.
+    fn print_columns(&self, output: &OutputConfig) {
+        let mut rows = vec![std::iter::once("device".to_string())
+            .chain(output.columns.iter().map(|column| column.header().to_string()))
+            .collect::<Vec<_>>()];
+        for device in self.devices.iter().filter(|device| device.avg_hz > 0.0) {
+            rows.push(
+                std::iter::once(device.name.clone())
+                    .chain(output.columns.iter().map(|column| column.value(output, device, self.duration)))
+                    .collect(),
+            );
+        }
+        let widths: Vec<usize> = (0..rows[0].len())
+            .map(|idx| rows.iter().map(|row| row[idx].chars().count()).max().unwrap_or(0))
+            .collect();
+        for row in &rows {
+            // Names left-aligned, figures right-aligned
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(idx, (cell, &width))| {
+                    if idx == 0 { format!("{:<width$}", cell) } else { format!("{:>width$}", cell) }
+                })
+                .collect();
+            println!("{}", cells.join("  "));
+        }
+    }
+
+    /// Warns about devices that averaged above their declared rate.
+    fn warn_over_declared(&self) {
+        for device in &self.devices {
+            if let Some(declared) = device.stats.over_declared() {
+                log_event(
+                    Severity::Warning,
+                    &format!(
+                        "{} averaged {:.0}Hz, more than its USB interface's declared {}Hz: \
+                         likely double reporting or a descriptor mismatch",
+                        device.name, device.stats.mean_hz, declared
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Warns about every measured device whose average fell below `--min-hz`.
+    fn warn_under_min(&self, output: &OutputConfig) {
+        let Some(min_hz) = output.min_hz else {
+            return;
+        };
+        for device in self.devices.iter().filter(|device| device.avg_hz > 0.0 && device.avg_hz < min_hz) {
+            log_event(
+                Severity::Warning,
+                &format!(
+                    "{} averaged {}{}, below --min-hz {}{}",
+                    device.name,
+                    output.format_hz(device.avg_hz),
+                    output.unit.suffix(),
+                    output.format_hz(min_hz),
+                    output.unit.suffix()
+                ),
+            );
+        }
+    }
 
-        if verbose {
+    /// Prints the text results for every device that measured anything.
+    fn print_text(&self, output: &OutputConfig) {
+        if output.verbosity > Verbosity::Quiet {
             println!();
         }
+        if let Some(label) = &output.label {
+            println!("Results for {}", label);
+        }
+        if output.verbosity == Verbosity::Verbose {
+            println!("Measured for {:.1}s", self.duration.as_secs_f64());
+        }
+        if !output.columns.is_empty() {
+            self.print_columns(output);
+        }
+        let unit = output.unit.suffix();
+        for device in &self.devices {
+            // The `--columns` table stands in for the average lines
+            if !output.columns.is_empty() {
+                device.stats.print_details(output);
+                continue;
+            }
+            device.stats.print_average(output, self.previous_hz(device));
+            if output.verbosity == Verbosity::Verbose && device.count > 0 {
+                let jitter = device.stats.jitter_us().map_or(String::new(), |us| format!(", jitter: {:.1}µs", us));
+                println!(
+                    "Range for {} {}{} to {}{}, stddev {}{} over {} samples{}",
+                    device.stats.label(),
+                    output.format_hz(device.min_hz as f64),
+                    unit,
+                    output.format_hz(device.max_hz as f64),
+                    unit,
+                    output.format_hz(device.stddev_hz),
+                    unit,
+                    device.count,
+                    jitter
+                );
+            }
+        }
+        #[cfg(target_os = "linux")]
+        for (label, timing) in &self.clicks {
+            timing.print(label);
+        }
+    }
 
-        // Use select to wait for events with timeout
-        use std::os::unix::io::AsRawFd;
+    /// The results as one JSON document, every measured device in it.
+    fn to_json(&self, output: &OutputConfig) -> Json {
+        let devices = self
+            .devices
+            .iter()
+            .filter(|device| device.avg_hz > 0.0)
+            .map(|device| device.to_json(output, self.previous.as_ref()))
+            .collect();
+        let mut fields = output.label_field();
+        fields.extend([
+            ("unit", Json::String(output.unit.suffix().to_string())),
+            ("duration_s", Json::Number(format!("{:.3}", self.duration.as_secs_f64()))),
+            ("devices", Json::Array(devices)),
+        ]);
+        Json::Object(fields)
+    }
+}
 
-        while running.load(Ordering::SeqCst) {
-            let mut fds: Vec<libc::pollfd> = devices.iter().map(|(_, device)| {
-                libc::pollfd {
-                    fd: device.as_raw_fd(),
-                                                                events: libc::POLLIN,
-                                                                revents: 0,
-                }
-            }).collect();
+/// Consecutive read errors after which a device is dropped from the poll set.
+#[cfg(target_os = "linux")]
+const MAX_READ_ERRORS: u32 = 5;
 
-            // Poll with 100ms timeout so we can check running flag
-            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 100) };
+/// Reports in one read past which the poll loop has fallen behind a device.
+#[cfg(target_os = "linux")]
+const BACKLOG_REPORTS: usize = 8;
 
-            if ret > 0 {
-                for (idx, (path, device)) in devices.iter_mut().enumerate() {
-                    if fds[idx].revents & libc::POLLIN != 0 {
-                        if let Ok(events) = device.fetch_events() {
-                            for event in events {
-                                match event.kind() {
-                                    InputEventKind::RelAxis(_) | InputEventKind::AbsAxis(_) => {
-                                        if let Some(stats) = stats_map.get_mut(path) {
-                                            stats.update(verbose);
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+/// How long `--auto-select` watches every device before keeping the busiest.
+#[cfg(target_os = "linux")]
+const AUTO_SELECT_AFTER: Duration = Duration::from_secs(2);
+
+/// How much the tool prints while measuring.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Only the final per-device averages.
+    Quiet,
+    /// Banner and final averages, no per-event lines.
+    Normal,
+    /// Device listing and a line for every measured event.
+    #[default]
+    Verbose,
+}
+
+/// Live display selected on the command line.
+#[derive(Clone, Default)]
+enum Display {
+    /// A line per measured event, subject to verbosity.
+    #[default]
+    Scroll,
+    /// Two devices head to head on a single refreshing line.
+    Compare(Compare),
+    /// The fastest N devices, re-sorted on every refresh.
+    Top(usize),
+    /// One device as a big full-screen number and bar.
+    Gauge(String),
+}
+
+/// When to write terminal escape sequences (cursor movement, color).
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum ColorChoice {
+    /// Only when stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
         }
+    }
 
-        println!();
-        for stats in stats_map.values() {
-            stats.print_average();
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => std::io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
         }
     }
-}
 
-#[cfg(target_os = "windows")]
-mod platform {
-    use super::*;
-    use windows::Win32::Foundation::POINT;
-    use windows::Win32::UI::Input::KeyboardAndMouse::GetAsyncKeyState;
-    use windows::Win32::UI::WindowsAndMessaging::GetCursorPos;
-
-    pub fn run(verbose: bool, running: Arc<AtomicBool>) {
-        if verbose {
-            println!("device0: Mouse");
-            println!("device1: Keyboard");
-            println!();
+    /// Whether to color device names. A non-empty `NO_COLOR` turns color off
+    /// under `auto`; `--color always` still wins.
+    fn colors(self) -> bool {
+        match self {
+            ColorChoice::Auto => self.enabled() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            other => other.enabled(),
         }
+    }
+}
+
+/// Foreground SGR codes device names are colored from, skipping black and
+/// white so every entry reads on dark and light terminals.
+const DEVICE_PALETTE: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
 
-        let mut mouse_stats = DeviceStats::new("Mouse".to_string());
-        let mut keyboard_stats = DeviceStats::new("Keyboard".to_string());
+/// The palette entry for a device, from an FNV-1a hash of its name so the
+/// same device keeps its color across refreshes and runs.
+fn device_color(name: &str) -> u8 {
+    let hash = name
+        .bytes()
+        .fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+    DEVICE_PALETTE[hash as usize % DEVICE_PALETTE.len()]
+}
 
-        let mut last_pos = POINT { x: 0, y: 0 };
-        let mut last_key_state = [false; 256];
+/// How Hz values are printed.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum HzFormat {
+    /// Whole Hz, rounded per `--round`.
+    #[default]
+    Int,
+    /// One decimal place.
+    Float,
+}
 
-        unsafe {
-            let _ = GetCursorPos(&mut last_pos);
+impl HzFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "int" => Some(HzFormat::Int),
+            "float" => Some(HzFormat::Float),
+            _ => None,
         }
+    }
 
-        while running.load(Ordering::SeqCst) {
-            // Check mouse movement
-            let mut current_pos = POINT { x: 0, y: 0 };
-            unsafe {
-                if GetCursorPos(&mut current_pos).is_ok() {
-                    if current_pos.x != last_pos.x || current_pos.y != last_pos.y {
-                        mouse_stats.update(verbose);
-                        last_pos = current_pos;
-                    }
-                }
+    /// Formats `hz`, which callers have already rounded for `Int`.
+    fn format(self, hz: f64) -> String {
+        match self {
+            HzFormat::Int => format!("{}", hz as i64),
+            HzFormat::Float => format!("{:.1}", hz),
+        }
+    }
 
-                // Check keyboard
-                for vk in 0..256 {
-                    let state = GetAsyncKeyState(vk) as u16 & 0x8000 != 0;
-                    if state && !last_key_state[vk as usize] {
-                        keyboard_stats.update(verbose);
-                    }
-                    last_key_state[vk as usize] = state;
-                }
-            }
+    /// Like `format`, right-aligned so columns line up.
+    fn padded(self, hz: f64) -> String {
+        match self {
+            HzFormat::Int => format!("{:5}", hz as i64),
+            HzFormat::Float => format!("{:7.1}", hz),
+        }
+    }
+}
+
+/// Unit rates are shown in.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+enum Unit {
+    #[default]
+    Hz,
+    /// Thousands of Hz, always with one decimal place.
+    KHz,
+}
 
-            std::thread::sleep(std::time::Duration::from_micros(100));
+impl Unit {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hz" => Some(Unit::Hz),
+            "khz" => Some(Unit::KHz),
+            _ => None,
         }
+    }
 
-        println!();
-        mouse_stats.print_average();
-        keyboard_stats.print_average();
+    fn suffix(self) -> &'static str {
+        match self {
+            Unit::Hz => "Hz",
+            Unit::KHz => "kHz",
+        }
     }
 }
 
-#[cfg(target_os = "macos")]
-mod platform {
-    use super::*;
-    use core_graphics::event::{CGEvent, CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement, CGEventType};
-    use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop};
-    use std::sync::Mutex;
+/// How a device's average is derived from its recent samples.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Smoothing {
+    /// Arithmetic mean of the last `window` samples.
+    Mean { window: usize },
+    /// Median of the last `window` samples, robust to the odd outlier.
+    Median { window: usize },
+    /// Exponentially weighted moving average; higher `alpha` reacts faster.
+    Ewma { alpha: f64 },
+}
 
-    lazy_static::lazy_static! {
-        static ref MOUSE_STATS: Mutex<DeviceStats> = Mutex::new(DeviceStats::new("Mouse".to_string()));
-        static ref KEYBOARD_STATS: Mutex<DeviceStats> = Mutex::new(DeviceStats::new("Keyboard".to_string()));
-        static ref VERBOSE: Mutex<bool> = Mutex::new(false);
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::Mean { window: HZ_LIST }
     }
+}
 
-    extern "C" fn event_callback(
-        _proxy: CGEventTapProxy,
-        event_type: CGEventType,
-        _event: CGEvent,
-        _user_info: *mut std::ffi::c_void,
-    ) -> Option<CGEvent> {
-        let verbose = *VERBOSE.lock().unwrap();
+impl Smoothing {
+    const DEFAULT_ALPHA: f64 = 0.1;
 
-        match event_type {
-            CGEventType::MouseMoved | CGEventType::LeftMouseDragged | CGEventType::RightMouseDragged => {
-                MOUSE_STATS.lock().unwrap().update(verbose);
-            }
-            CGEventType::KeyDown | CGEventType::KeyUp => {
-                KEYBOARD_STATS.lock().unwrap().update(verbose);
+    /// Parses `mean`, `median` or `ewma`, optionally followed by `:N` for the
+    /// window size or `:ALPHA` for the EWMA weight.
+    fn parse(value: &str) -> Option<Self> {
+        let (kind, param) = match value.split_once(':') {
+            Some((kind, param)) => (kind, Some(param)),
+            None => (value, None),
+        };
+        let window = || match param {
+            Some(param) => param.parse().ok().filter(|&n| n > 0),
+            None => Some(HZ_LIST),
+        };
+
+        match kind {
+            "mean" => Some(Smoothing::Mean { window: window()? }),
+            "median" => Some(Smoothing::Median { window: window()? }),
+            "ewma" => {
+                let alpha = match param {
+                    Some(param) => param.parse().ok().filter(|&a| a > 0.0 && a <= 1.0)?,
+                    None => Self::DEFAULT_ALPHA,
+                };
+                Some(Smoothing::Ewma { alpha })
             }
-            _ => {}
+            _ => None,
         }
+    }
 
-        None
+    /// Window size chosen by the user, if the strategy keeps one.
+    fn fixed_window(self) -> Option<usize> {
+        match self {
+            Smoothing::Mean { window } | Smoothing::Median { window } => Some(window),
+            Smoothing::Ewma { .. } => None,
+        }
     }
 
-    pub fn run(verbose: bool, running: Arc<AtomicBool>) {
-        *VERBOSE.lock().unwrap() = verbose;
+    /// Number of samples kept per device.
+    fn window(self) -> usize {
+        match self {
+            Smoothing::Mean { window } | Smoothing::Median { window } => window,
+            Smoothing::Ewma { .. } => HZ_LIST,
+        }
+    }
+}
 
-        if verbose {
-            println!("device0: Mouse");
-            println!("device1: Keyboard");
-            println!();
+/// How the final per-device results are written.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum SummaryFormat {
+    /// One `Average for ...` line per device.
+    #[default]
+    Text,
+    /// A single JSON document on one line.
+    Json,
+    /// The same document, indented.
+    JsonPretty,
+}
+
+/// Which retained samples the exit and live-display averages leave out.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Trim {
+    /// Keep every sample and report the smoothing strategy's value.
+    #[default]
+    None,
+    /// Average only samples within 1.5×IQR of the quartiles.
+    Iqr,
+}
+
+impl Trim {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Trim::None),
+            "iqr" => Some(Trim::Iqr),
+            _ => None,
+        }
+    }
+
+    fn average(self, stats: &DeviceStats) -> f64 {
+        match self {
+            Trim::None => stats.avg_hz_exact,
+            // Too few samples for quartiles to mean anything
+            Trim::Iqr if stats.hz_history.len() < 4 => stats.avg_hz_exact,
+            Trim::Iqr => {
+                let mut sorted: Vec<u32> = stats.hz_history.iter().copied().collect();
+                sorted.sort_unstable();
+                let (q1, q3) = (sorted[sorted.len() / 4] as f64, sorted[sorted.len() * 3 / 4] as f64);
+                let reach = 1.5 * (q3 - q1);
+                let inliers: Vec<f64> = sorted
+                    .iter()
+                    .map(|&hz| hz as f64)
+                    .filter(|&hz| hz >= q1 - reach && hz <= q3 + reach)
+                    .collect();
+                inliers.iter().sum::<f64>() / inliers.len() as f64
+            }
         }
+    }
+}
 
-        let event_tap = CGEventTap::new(
-            CGEventTapLocation::HID,
-            CGEventTapPlacement::HeadInsertEventTap,
-            CGEventTapOptions::ListenOnly,
-            vec![
-                CGEventType::MouseMoved,
-                CGEventType::LeftMouseDragged,
-                CGEventType::RightMouseDragged,
-                CGEventType::KeyDown,
-                CGEventType::KeyUp,
-            ],
-            event_callback,
-        )
-        .expect("Failed to create event tap. Run with sudo.");
+/// A field the text summary shows per device with `--columns`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Column {
+    Avg,
+    Min,
+    Max,
+    Stddev,
+    /// 99th percentile of the retained window.
+    P99,
+    /// Mean change between adjacent intervals of the retained window.
+    Jitter,
+    Count,
+    Duration,
+    /// The USB interface's declared rate, `-` when unknown.
+    Declared,
+}
 
-        let loop_source = event_tap
-        .mach_port
-        .create_runloop_source(0)
-        .expect("Failed to create runloop source");
+impl Column {
+    const NAMES: [(&str, Column); 9] = [
+        ("avg", Column::Avg),
+        ("min", Column::Min),
+        ("max", Column::Max),
+        ("stddev", Column::Stddev),
+        ("p99", Column::P99),
+        ("jitter", Column::Jitter),
+        ("count", Column::Count),
+        ("duration", Column::Duration),
+        ("declared", Column::Declared),
+    ];
 
-        let run_loop = CFRunLoop::get_current();
-        run_loop.add_source(&loop_source, unsafe { kCFRunLoopCommonModes });
-        event_tap.enable();
+    /// Parses a comma-separated list, keeping its order. `Err` carries the
+    /// first unknown name.
+    fn parse_list(value: &str) -> Result<Vec<Self>, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .map(|name| {
+                Self::NAMES
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                    .map(|&(_, column)| column)
+                    .ok_or_else(|| name.to_string())
+            })
+            .collect()
+    }
+
+    fn header(self) -> &'static str {
+        Self::NAMES.iter().find(|(_, column)| *column == self).map_or("", |(name, _)| name)
+    }
 
-        while running.load(Ordering::SeqCst) {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+    fn value(self, output: &OutputConfig, device: &DeviceSummary, duration: Duration) -> String {
+        let hz = |hz: f64| format!("{}{}", output.format_hz(hz), output.unit.suffix());
+        match self {
+            Column::Avg => hz(device.avg_hz),
+            Column::Min => hz(device.min_hz as f64),
+            Column::Max => hz(device.max_hz as f64),
+            Column::Stddev => hz(device.stddev_hz),
+            Column::P99 => device.stats.percentile_hz(99).map_or("-".to_string(), |p99| hz(p99 as f64)),
+            Column::Jitter => device.stats.jitter_us().map_or("-".to_string(), |us| format!("{:.1}µs", us)),
+            Column::Count => device.count.to_string(),
+            Column::Duration => format!("{:.1}s", duration.as_secs_f64()),
+            Column::Declared => device.stats.declared_hz.map_or("-".to_string(), |declared| hz(declared as f64)),
         }
+    }
+}
 
-        println!();
-        MOUSE_STATS.lock().unwrap().print_average();
-        KEYBOARD_STATS.lock().unwrap().print_average();
+/// How fractional Hz become the whole numbers shown with `--format-hz int`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Rounding {
+    Floor,
+    /// Half away from zero, so 999.5Hz shows as 1000Hz.
+    #[default]
+    Nearest,
+    Ceil,
+}
+
+impl Rounding {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "floor" => Some(Rounding::Floor),
+            "nearest" => Some(Rounding::Nearest),
+            "ceil" => Some(Rounding::Ceil),
+            _ => None,
+        }
+    }
+
+    fn apply(self, hz: f64) -> f64 {
+        match self {
+            Rounding::Floor => hz.floor(),
+            Rounding::Nearest => hz.round(),
+            Rounding::Ceil => hz.ceil(),
+        }
     }
 }
 
-#[cfg(target_os = "freebsd")]
-mod platform {
-    use super::*;
-    // FreeBSD uses same evdev approach as Linux
-    pub use super::platform::run;
+/// Output settings chosen on the command line and shared by every backend.
+#[derive(Clone, Default)]
+struct OutputConfig {
+    verbosity: Verbosity,
+    display: Display,
+    /// Refreshing displays redraw in place; without it they append lines,
+    /// which keeps piped output free of escape sequences.
+    ansi: bool,
+    hz_format: HzFormat,
+    unit: Unit,
+    rounding: Rounding,
+    /// Keep raw intervals and look for known artifacts at exit.
+    analyze: bool,
+    summary: SummaryFormat,
+    /// Skip the final results entirely, for pure streaming.
+    no_summary: bool,
+    /// Skip the startup banner even when not quiet.
+    no_banner: bool,
+    /// Names the session in the banner, summary and structured output.
+    label: Option<String>,
+    /// Feed every counted event into one aggregate `SYSTEM_NAME` entry.
+    system: bool,
+    smoothing: Smoothing,
+    /// Keep the samples of this last stretch of time for the mean or median
+    /// instead of a fixed count, with `--window-ms`.
+    window_span: Option<Duration>,
+    /// Soft cap in bytes on what the per-device sample buffers may retain.
+    memory_budget: Option<usize>,
+    /// Live displays show events in the trailing `RATE_WINDOW` instead of
+    /// the interval average, so they fall to zero when input stops.
+    windowed: bool,
+    /// Report no average until a device has this many samples.
+    min_samples: usize,
+    /// Keep this many latest raw intervals per device and print them at exit.
+    tail: usize,
+    /// Print every device's current average this often.
+    checkpoint: Option<Duration>,
+    /// Checkpoints as terse `index rate` lines for slow links.
+    remote: bool,
+    /// Count intervals into `GAP_BUCKETS` and print the split at exit.
+    gaps: bool,
+    /// Print each device's most common rate at exit.
+    mode_rate: bool,
+    /// Also count events per wall-clock second, next to the interval Hz.
+    count_rate: bool,
+    /// Color each device's live lines by its name.
+    color: bool,
+    /// Keep a status line with the fastest device's rate on stderr.
+    progress: bool,
+    /// Outlier rejection for the exit and live-display averages.
+    trim: Trim,
+    /// Fields of the text summary's table, replacing the average lines.
+    columns: Vec<Column>,
+    /// Only monitor the first device whose name contains this.
+    device: Option<String>,
+    /// Sample for `PROBE_WINDOW` or `--duration`, then print one bare average.
+    probe: bool,
+    /// Compare the averages to the last run's and cache them for the next.
+    compare_previous: bool,
+    /// Follow each live average with its share of the session's peak rate.
+    relative: bool,
+    /// Calibrated per-event stats overhead taken off every interval.
+    overhead_ns: u64,
+    /// Latest event `Timestamp` on any device, kept for `--timeout-idle`.
+    last_event: Option<Arc<AtomicU64>>,
+    /// Event times come from `--stdin` or `--replay` input, not this run's
+    /// clock.
+    recorded: bool,
+    /// Rate bands checked against the exit averages.
+    asserts: Vec<RateAssertion>,
+    /// Warn at exit about devices averaging below this rate.
+    min_hz: Option<f64>,
+    /// Send runtime warnings to syslog, keeping stdout to the summary.
+    #[cfg(unix)]
+    syslog: bool,
+    /// Buffer for per-event lines with `--flush-interval`.
+    batched: Option<Arc<std::sync::Mutex<BatchedStdout>>>,
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let mut verbose = true;
-
-    for arg in &args[1..] {
-        match arg.as_str() {
-            "-h" | "--help" => {
-                println!("Usage: {} [-n|-h]", args[0]);
-                println!("-n, --nonverbose    nonverbose mode");
-                println!("-h, --help          show this help");
-                return;
-            }
-            "-n" | "--nonverbose" => {
-                verbose = false;
-            }
-            _ => {
-                eprintln!("Unknown option: {}", arg);
-                return;
-            }
+impl OutputConfig {
+    /// Prints one per-event line, buffered with `--flush-interval`.
+    fn event_line(&self, line: &str) {
+        match &self.batched {
+            Some(batched) => batched.lock().unwrap().line(line),
+            None => println!("{}", line),
         }
     }
 
-    #[cfg(target_os = "linux")]
-    {
-        // Check if we can access /dev/input
-        if std::fs::metadata("/dev/input/event0").is_err() {
-            eprintln!("Cannot access /dev/input devices.");
-            eprintln!("To run without root, add your user to the 'input' group:");
-            eprintln!("  sudo usermod -aG input $USER");
-            eprintln!("Then log out and log back in, or run with sudo.");
-            std::process::exit(1);
+    /// Writes out buffered per-event lines before anything else is printed.
+    fn flush_events(&self) {
+        if let Some(batched) = &self.batched {
+            batched.lock().unwrap().flush();
         }
     }
 
-    println!("Press CTRL-C to exit.\n");
+    /// `text` in the color of device `name`, when coloring.
+    fn paint(&self, name: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", device_color(name), text)
+        } else {
+            text.to_string()
+        }
+    }
 
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    fn format_hz(&self, hz: f64) -> String {
+        match self.unit {
+            Unit::Hz => self.hz_format.format(self.round_hz(hz)),
+            Unit::KHz => format!("{:.1}", hz / 1000.0),
+        }
+    }
 
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    /// Like `format_hz` with a sign, `+` for zero, taken from the figure as
+    /// shown so a small negative change that rounds away reads `+0`.
+    fn signed_hz(&self, hz: f64) -> String {
+        let text = self.format_hz(hz);
+        match text.strip_prefix('-') {
+            Some(magnitude) if magnitude.bytes().any(|b| b.is_ascii_digit() && b != b'0') => text,
+            Some(magnitude) => format!("+{}", magnitude),
+            None => format!("+{}", text),
+        }
+    }
+
+    /// Like `format_hz`, right-aligned so columns line up.
+    fn padded_hz(&self, hz: f64) -> String {
+        match self.unit {
+            Unit::Hz => self.hz_format.padded(self.round_hz(hz)),
+            Unit::KHz => format!("{:5.1}", hz / 1000.0),
+        }
+    }
+
+    /// Whole Hz per `--round` for integer output; float output keeps the fraction.
+    fn round_hz(&self, hz: f64) -> f64 {
+        match self.hz_format {
+            HzFormat::Int => self.rounding.apply(hz),
+            HzFormat::Float => hz,
+        }
+    }
+
+    /// Worst-case bytes one device retains: its Hz and jitter windows, the
+    /// median's sorted counts, and the raw intervals with `--analyze` and
+    /// `--tail`.
+    fn bytes_per_device(&self, window: usize) -> usize {
+        let intervals = if self.analyze { HZ_LIST * 8 } else { 0 };
+        window * self.bytes_per_sample() + intervals + self.tail * 8
+    }
+
+    /// Bytes each sample of the window costs, a B-tree entry of about 16
+    /// bytes on top for the median.
+    fn bytes_per_sample(&self) -> usize {
+        match self.smoothing {
+            Smoothing::Median { .. } => 24,
+            _ => 8,
+        }
+    }
+
+    /// Shrinks the averaging window when `devices` full buffers would exceed
+    /// `--max-memory-mb`, so the average becomes an approximation over fewer
+    /// samples instead of growing without bound.
+    fn fit_memory(&self, devices: usize) -> OutputConfig {
+        let mut fitted = self.clone();
+        let (Some(budget), Some(window)) = (self.memory_budget, self.smoothing.fixed_window()) else {
+            return fitted;
+        };
+        let per_device = budget / devices.max(1);
+        if self.bytes_per_device(window) <= per_device {
+            return fitted;
+        }
+
+        let shrunk = (per_device.saturating_sub(self.bytes_per_device(0)) / self.bytes_per_sample()).max(1);
+        fitted.smoothing = match self.smoothing {
+            Smoothing::Median { .. } => Smoothing::Median { window: shrunk },
+            _ => Smoothing::Mean { window: shrunk },
+        };
+        log_event(
+            Severity::Warning,
+            &format!("averaging window shrunk from {} to {} samples to stay under --max-memory-mb", window, shrunk),
+        );
+        fitted
+    }
 
-    platform::run(verbose, running);
+    /// A leading `label` field for JSON documents and records, with `--label`.
+    fn label_field(&self) -> Vec<(&'static str, Json)> {
+        self.label.iter().map(|label| ("label", Json::String(label.clone()))).collect()
+    }
+
+    /// Per-event lines are only printed in scrolling mode; the refreshing
+    /// displays own the terminal lines they draw on.
+    fn shows_events(&self) -> bool {
+        self.verbosity == Verbosity::Verbose && matches!(self.display, Display::Scroll)
+    }
+}
+
+/// One accepted sample, as handed to every `OutputSink`.
+struct HzSample<'a> {
+    /// The device's stats just after the sample; `avg_hz_exact` stays zero
+    /// while still measuring.
+    stats: &'a DeviceStats,
+    time: Timestamp,
+    /// Rate implied by this interval alone.
+    hz: f64,
+}
+
+impl HzSample<'_> {
+    /// The sample as an NDJSON record.
+    fn to_json(&self, output: &OutputConfig) -> Json {
+        let mut fields = vec![("time", Json::String(now_iso8601()))];
+        fields.extend(output.label_field());
+        fields.extend([
+            ("device", Json::String(self.stats.name.clone())),
+            ("hz", Json::hz(output, self.hz)),
+            ("avg_hz", Json::hz(output, self.stats.avg_hz_exact)),
+            ("unit", Json::String(output.unit.suffix().to_string())),
+        ]);
+        Json::Object(fields)
+    }
+
+    /// The verbose per-event line.
+    fn event_line(&self, output: &OutputConfig) -> String {
+        let stats = self.stats;
+        let unit = output.unit.suffix();
+        let measuring = stats.avg_hz_exact == 0.0;
+        let line = match stats.declared_hz {
+            _ if measuring => format!(
+                "{} Latest {}{}, Average measuring...",
+                output.paint(&stats.name, &stats.label()),
+                output.padded_hz(self.hz),
+                unit
+            ),
+            Some(declared) => format!(
+                "{} Latest {}{}, declared {}{} / measured {}{} ({:.1}%)",
+                output.paint(&stats.name, &stats.label()),
+                output.padded_hz(self.hz),
+                unit,
+                output.format_hz(declared as f64),
+                unit,
+                output.padded_hz(stats.avg_hz_exact),
+                unit,
+                stats.avg_hz_exact * 100.0 / declared as f64
+            ),
+            None => format!(
+                "{} Latest {}{}, Average {}{}",
+                output.paint(&stats.name, &stats.label()),
+                output.padded_hz(self.hz),
+                unit,
+                output.padded_hz(stats.avg_hz_exact),
+                unit
+            ),
+        };
+        let mut line = match stats.counted_hz {
+            Some(counted) if output.count_rate => {
+                format!("{}, counted {}{}", line, output.padded_hz(counted as f64), unit)
+            }
+            _ => line,
+        };
+        if output.relative
+            && !measuring
+            && let Some(pct) = stats.peak_pct()
+        {
+            line += &format!(
+                ", {:.0}% of peak ({}/{}{})",
+                pct,
+                output.format_hz(stats.avg_hz_exact),
+                output.format_hz(stats.max_hz as f64),
+                unit
+            );
+        }
+        line
+    }
+}
+
+/// A device joining or leaving the measurement, for live consumers to add
+/// or drop its row. `index` is its place in the monitored list.
+struct DeviceChange<'a> {
+    added: bool,
+    index: usize,
+    name: &'a str,
+}
+
+impl DeviceChange<'_> {
+    /// The change as an NDJSON record, told apart from samples by `event`.
+    fn to_json(&self, output: &OutputConfig) -> Json {
+        let event = if self.added { "device_added" } else { "device_removed" };
+        let mut fields = vec![
+            ("event", Json::String(event.to_string())),
+            ("time", Json::String(now_iso8601())),
+        ];
+        fields.extend(output.label_field());
+        fields.extend([
+            ("name", Json::String(self.name.to_string())),
+            ("index", Json::Number(self.index.to_string())),
+        ]);
+        Json::Object(fields)
+    }
+}
+
+/// A destination for what the session measures: stdout's console, JSON or
+/// `--probe` output, and each of `--fifo`, `--csv`, `--gnuplot`,
+/// `--prometheus`, `--capture` and `--split-output`, in any combination.
+trait OutputSink {
+    /// Called as devices join the measurement and when one drops out.
+    fn on_device(&mut self, _output: &OutputConfig, _change: &DeviceChange) {}
+    /// Called for every event a device records, before any filtering.
+    fn on_event(&mut self, _output: &OutputConfig, _device: &str, _time: Timestamp) {}
+    fn on_sample(&mut self, _output: &OutputConfig, _sample: &HzSample) {}
+    /// Called once, with the last run's results and the runs before it.
+    fn on_summary(&mut self, _output: &OutputConfig, _summary: &RunSummary) {}
+    /// Called last, also when a run failed, to complete what was written.
+    fn on_close(&mut self, _output: &OutputConfig) {}
+}
+
+/// The session's sinks, stdout's first. `main` lends them to every run and
+/// closes them once the runs are over or one failed.
+#[derive(Default)]
+struct Sinks(Vec<Box<dyn OutputSink>>);
+
+impl Sinks {
+    fn push(&mut self, sink: impl OutputSink + 'static) {
+        self.0.push(Box::new(sink));
+    }
+
+    /// Tells every sink a device joined or left the measurement.
+    fn device(&mut self, output: &OutputConfig, added: bool, index: usize, name: &str) {
+        let change = DeviceChange { added, index, name };
+        for sink in &mut self.0 {
+            sink.on_device(output, &change);
+        }
+    }
+
+    fn event(&mut self, output: &OutputConfig, device: &str, time: Timestamp) {
+        for sink in &mut self.0 {
+            sink.on_event(output, device, time);
+        }
+    }
+
+    fn sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        for sink in &mut self.0 {
+            sink.on_sample(output, sample);
+        }
+    }
+
+    fn summary(&mut self, output: &OutputConfig, summary: &RunSummary) {
+        for sink in &mut self.0 {
+            sink.on_summary(output, summary);
+        }
+    }
+
+    fn close(&mut self, output: &OutputConfig) {
+        for sink in &mut self.0 {
+            sink.on_close(output);
+        }
+    }
+}
+
+/// Stdout in text: the verbose per-event lines, then the results, each run's
+/// under a heading with `--runs`.
+struct ConsoleSink;
+
+impl OutputSink for ConsoleSink {
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        if output.shows_events() {
+            output.event_line(&sample.event_line(output));
+        }
+    }
+
+    fn on_summary(&mut self, output: &OutputConfig, summary: &RunSummary) {
+        let runs = summary.runs();
+        for (idx, run) in runs.iter().enumerate() {
+            if runs.len() > 1 && !output.no_summary {
+                println!("{}Run {} of {}", if idx > 0 { "\n" } else { "" }, idx + 1, runs.len());
+            }
+            if !output.no_summary {
+                run.print_text(output);
+            }
+            run.warn_over_declared();
+            run.warn_under_min(output);
+            #[cfg(target_os = "linux")]
+            for info in &run.device_info {
+                print!("\n{}", info);
+            }
+        }
+        if runs.len() > 1 && !output.no_summary {
+            print_spreads(output, &runs);
+        }
+    }
+}
+
+/// Stdout as one JSON document, for `--json` and `--json-pretty`: the
+/// results, or only how averages spread with `--runs`.
+struct JsonSink {
+    indent: Option<usize>,
+}
+
+impl OutputSink for JsonSink {
+    fn on_summary(&mut self, output: &OutputConfig, summary: &RunSummary) {
+        let runs = summary.runs();
+        if !output.no_summary {
+            let document = if runs.len() > 1 { spreads_json(output, &runs) } else { summary.to_json(output) };
+            println!("{}", document.to_string(self.indent));
+        }
+        // Kept off stdout, which carries the document
+        for run in &runs {
+            run.warn_over_declared();
+            run.warn_under_min(output);
+            #[cfg(target_os = "linux")]
+            for info in &run.device_info {
+                eprint!("\n{}", info);
+            }
+        }
+    }
+}
+
+/// Stdout with `--probe`: one bare average and nothing else.
+struct ProbeSink;
+
+impl OutputSink for ProbeSink {
+    fn on_summary(&mut self, _output: &OutputConfig, summary: &RunSummary) {
+        if let Some(hz) = summary.probe_hz() {
+            println!("{}", hz);
+        }
+    }
+}
+
+/// Writes NDJSON lines to a named pipe for external frontends. The pipe is
+/// opened non-blocking, so without a reader, or with one that can't keep
+/// up, lines are dropped instead of stalling the measurement.
+#[cfg(unix)]
+struct FifoSink {
+    path: std::path::PathBuf,
+    file: Option<std::fs::File>,
+    last_open_attempt: Option<Instant>,
+}
+
+#[cfg(unix)]
+impl FifoSink {
+    /// How often to look for a reader while none is connected.
+    const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates the FIFO at `path` unless something already exists there.
+    fn create(path: &str) -> std::io::Result<Self> {
+        use std::os::unix::ffi::OsStrExt;
+
+        if std::fs::metadata(path).is_err() {
+            let c_path = std::ffi::CString::new(std::ffi::OsStr::new(path).as_bytes())?;
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(Self {
+            path: path.into(),
+            file: None,
+            last_open_attempt: None,
+        })
+    }
+
+    fn send(&mut self, line: &str) {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        if self.file.is_none() {
+            if self.last_open_attempt.is_some_and(|last| last.elapsed() < Self::REOPEN_INTERVAL) {
+                return;
+            }
+            self.last_open_attempt = Some(Instant::now());
+            // Fails with ENXIO while nobody has the pipe open for reading
+            self.file = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path)
+                .ok();
+        }
+
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        // Lines are shorter than PIPE_BUF, so each write lands whole or not
+        // at all; a full pipe (EAGAIN) just drops this sample
+        let mut buf = line.as_bytes().to_vec();
+        buf.push(b'\n');
+        if let Err(err) = file.write(&buf)
+            && err.kind() == std::io::ErrorKind::BrokenPipe
+        {
+            self.file = None;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl OutputSink for FifoSink {
+    fn on_device(&mut self, output: &OutputConfig, change: &DeviceChange) {
+        self.send(&change.to_json(output).to_string(None));
+    }
+
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        self.send(&sample.to_json(output).to_string(None));
+    }
+}
+
+/// `time,device,hz,avg_hz` rows for `--csv`, one per sample, after a
+/// `label` column with `--label`. `time` is wall-clock time, or for
+/// recorded input the seconds since its first event.
+struct CsvSink {
+    file: std::io::BufWriter<std::fs::File>,
+    header: bool,
+    /// The session's first event, which recorded times count from.
+    start: Option<Timestamp>,
+}
+
+impl CsvSink {
+    fn create(path: &str) -> std::io::Result<Self> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        Ok(Self {
+            file,
+            header: false,
+            start: None,
+        })
+    }
+
+    fn time(&self, output: &OutputConfig, time: Timestamp) -> String {
+        match self.start {
+            Some(start) if output.recorded => format!("{:.6}", time.nanos_since(start) as f64 / 1e9),
+            _ => now_iso8601(),
+        }
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn on_event(&mut self, _output: &OutputConfig, _device: &str, time: Timestamp) {
+        self.start.get_or_insert(time);
+    }
+
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        let quoted = |text: &str| format!("\"{}\",", text.replace('"', "\"\""));
+        let label = output.label.as_deref().map(quoted).unwrap_or_default();
+        // Only known once every option is parsed
+        if !self.header {
+            let _ = writeln!(self.file, "{}time,device,hz,avg_hz", if label.is_empty() { "" } else { "label," });
+            self.header = true;
+        }
+        let row = writeln!(
+            self.file,
+            "{}{},{}{},{}",
+            label,
+            self.time(output, sample.time),
+            quoted(&sample.stats.name),
+            output.format_hz(sample.hz),
+            output.format_hz(sample.stats.avg_hz_exact)
+        );
+        if let Err(err) = row {
+            log_event(Severity::Warning, &format!("--csv: failed to write a row: {}", err));
+        }
+    }
+
+    fn on_close(&mut self, _output: &OutputConfig) {
+        if let Err(err) = self.file.flush() {
+            log_event(Severity::Error, &format!("--csv: failed to write the file: {}", err));
+        }
+    }
+}
+
+/// One NDJSON record per sample and device change, as `--fifo` sends them,
+/// to a plain file.
+struct NdjsonSink {
+    file: std::io::BufWriter<std::fs::File>,
+    /// The option the file belongs to, for error messages.
+    option: &'static str,
+}
+
+impl NdjsonSink {
+    fn write(&mut self, record: Json) {
+        if let Err(err) = writeln!(self.file, "{}", record.to_string(None)) {
+            log_event(Severity::Warning, &format!("{}: failed to write a record: {}", self.option, err));
+        }
+    }
+}
+
+impl OutputSink for NdjsonSink {
+    fn on_device(&mut self, output: &OutputConfig, change: &DeviceChange) {
+        self.write(change.to_json(output));
+    }
+
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        self.write(sample.to_json(output));
+    }
+
+    fn on_close(&mut self, _output: &OutputConfig) {
+        if let Err(err) = self.file.flush() {
+            log_event(Severity::Error, &format!("{}: failed to write a file: {}", self.option, err));
+        }
+    }
+}
+
+/// File format of `--split-output`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum SplitFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl SplitFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            SplitFormat::Csv => "csv",
+            SplitFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// `--split-output`: every device's samples in a file of its own under one
+/// directory, opened when the device first reports, or joins with NDJSON.
+struct SplitSink {
+    dir: std::path::PathBuf,
+    format: SplitFormat,
+    /// Each device's file, or `None` if it couldn't be created.
+    files: Vec<(String, Option<Box<dyn OutputSink>>)>,
+    taken: std::collections::HashSet<String>,
+    /// The session's first event, so every CSV file counts recorded times
+    /// from the same point.
+    start: Option<Timestamp>,
+}
+
+impl SplitSink {
+    fn create(dir: &str, format: SplitFormat) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.into(),
+            format,
+            files: Vec::new(),
+            taken: std::collections::HashSet::new(),
+            start: None,
+        })
+    }
+
+    /// A file name for `device`, numbering names that would otherwise clash.
+    fn file_name(&mut self, device: &str) -> String {
+        let stem = file_stem(device);
+        let mut name = format!("{}.{}", stem, self.format.extension());
+        let mut n = 2;
+        while !self.taken.insert(name.clone()) {
+            name = format!("{}-{}.{}", stem, n, self.format.extension());
+            n += 1;
+        }
+        name
+    }
+
+    fn open(&mut self, device: &str) -> Option<Box<dyn OutputSink>> {
+        let name = self.file_name(device);
+        let path = self.dir.join(name);
+        let sink: std::io::Result<Box<dyn OutputSink>> = match self.format {
+            SplitFormat::Csv => CsvSink::create(&path.to_string_lossy()).map(|sink| {
+                Box::new(CsvSink {
+                    start: self.start,
+                    ..sink
+                }) as _
+            }),
+            SplitFormat::Ndjson => std::fs::File::create(&path).map(|file| {
+                Box::new(NdjsonSink {
+                    file: std::io::BufWriter::new(file),
+                    option: "--split-output",
+                }) as _
+            }),
+        };
+        sink.map_err(|err| {
+            log_event(
+                Severity::Error,
+                &format!("--split-output: cannot create {}: {}", path.display(), err),
+            )
+        })
+        .ok()
+    }
+
+    /// `device`'s file, opened on first use.
+    fn file(&mut self, device: &str) -> Option<&mut Box<dyn OutputSink>> {
+        let idx = match self.files.iter().position(|(name, _)| name == device) {
+            Some(idx) => idx,
+            None => {
+                let file = self.open(device);
+                self.files.push((device.to_string(), file));
+                self.files.len() - 1
+            }
+        };
+        self.files[idx].1.as_mut()
+    }
+}
+
+/// `name` cut down to letters, digits, `-`, `_` and `.` for use in a file
+/// name, everything else becoming `_`.
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    // No hidden files, and no `.` or `..`
+    match stem.trim_matches('.') {
+        "" => "device".to_string(),
+        stem => stem.to_string(),
+    }
+}
+
+impl OutputSink for SplitSink {
+    fn on_event(&mut self, _output: &OutputConfig, _device: &str, time: Timestamp) {
+        self.start.get_or_insert(time);
+    }
+
+    /// Only NDJSON files record changes, so CSV ones still wait for a sample.
+    fn on_device(&mut self, output: &OutputConfig, change: &DeviceChange) {
+        if self.format == SplitFormat::Ndjson
+            && let Some(file) = self.file(change.name)
+        {
+            file.on_device(output, change);
+        }
+    }
+
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        if let Some(file) = self.file(&sample.stats.name) {
+            file.on_sample(output, sample);
+        }
+    }
+
+    fn on_close(&mut self, output: &OutputConfig) {
+        for file in self.files.iter_mut().filter_map(|(_, file)| file.as_mut()) {
+            file.on_close(output);
+        }
+    }
+}
+
+/// The exit results as a node_exporter textfile-collector file for
+/// `--prometheus`, always in Hz, Prometheus' base unit.
+struct PrometheusSink {
+    path: String,
+}
+
+impl PrometheusSink {
+    fn render(output: &OutputConfig, summary: &RunSummary) -> String {
+        let escape = |name: &str| name.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let session = output.label.as_deref().map(|label| format!(",label=\"{}\"", escape(label))).unwrap_or_default();
+        let metrics = [
+            ("evhz_avg_hz", "Average report rate at exit."),
+            ("evhz_min_hz", "Slowest sample rate of the run."),
+            ("evhz_max_hz", "Fastest sample rate of the run."),
+            ("evhz_samples", "Samples counted over the run."),
+        ];
+        let mut text = String::new();
+        for (metric, help) in metrics {
+            text += &format!("# HELP {} {}\n# TYPE {} gauge\n", metric, help, metric);
+            for device in summary.devices.iter().filter(|device| device.avg_hz > 0.0) {
+                let value = match metric {
+                    "evhz_avg_hz" => device.avg_hz,
+                    "evhz_min_hz" => device.min_hz as f64,
+                    "evhz_max_hz" => device.max_hz as f64,
+                    _ => device.count as f64,
+                };
+                text += &format!("{}{{device=\"{}\"{}}} {}\n", metric, escape(&device.name), session, value);
+            }
+        }
+        text
+    }
+}
+
+impl OutputSink for PrometheusSink {
+    fn on_summary(&mut self, output: &OutputConfig, summary: &RunSummary) {
+        // Renamed into place so the collector never reads half a file
+        let partial = format!("{}.part", self.path);
+        let written = std::fs::write(&partial, Self::render(output, summary)).and_then(|()| std::fs::rename(&partial, &self.path));
+        if let Err(err) = written {
+            log_event(Severity::Error, &format!("--prometheus: failed to write {}: {}", self.path, err));
+        }
+    }
+}
+
+/// Spools `elapsed_us avg_hz` rows for `--gnuplot` while measuring, then on
+/// exit regroups them into one data block per device and writes a script
+/// plotting every block, so the series waits on disk rather than in memory.
+struct GnuplotSink {
+    prefix: String,
+    spool: std::io::BufWriter<std::fs::File>,
+    /// Devices in the order they first reported, numbering the blocks.
+    names: Vec<String>,
+    started: Option<Timestamp>,
+}
+
+impl GnuplotSink {
+    fn create(prefix: &str) -> std::io::Result<Self> {
+        let spool = std::fs::File::create(Self::spool_path(prefix))?;
+        Ok(Self {
+            prefix: prefix.to_string(),
+            spool: std::io::BufWriter::new(spool),
+            names: Vec::new(),
+            started: None,
+        })
+    }
+
+    fn spool_path(prefix: &str) -> String {
+        format!("{}.dat.part", prefix)
+    }
+
+    /// Times start at the first sample of any device.
+    fn record(&mut self, name: &str, time: Timestamp, avg_hz: &str) {
+        let started = *self.started.get_or_insert(time);
+        let idx = match self.names.iter().position(|seen| seen == name) {
+            Some(idx) => idx,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        };
+        // Disk errors surface when `finish` reads the spool back
+        let _ = writeln!(self.spool, "{} {} {}", idx, time.nanos_since(started) / 1000, avg_hz);
+    }
+
+    /// Writes `<prefix>.dat` with a block per device and `<prefix>.plt`.
+    fn finish(&mut self, output: &OutputConfig) -> std::io::Result<()> {
+        use std::io::BufRead;
+
+        self.spool.flush()?;
+        let spool = Self::spool_path(&self.prefix);
+        let (data, script) = (format!("{}.dat", self.prefix), format!("{}.plt", self.prefix));
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&data)?);
+        // One pass per device keeps memory flat however long the run
+        for (idx, name) in self.names.iter().enumerate() {
+            writeln!(out, "# {}", name)?;
+            let tag = format!("{} ", idx);
+            for line in std::io::BufReader::new(std::fs::File::open(&spool)?).lines() {
+                if let Some(row) = line?.strip_prefix(&tag) {
+                    writeln!(out, "{}", row)?;
+                }
+            }
+            // Two blank lines end a gnuplot `index` block
+            writeln!(out, "\n")?;
+        }
+        out.flush()?;
+        std::fs::remove_file(&spool)?;
+
+        let quoted = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let series: Vec<String> = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| {
+                format!("{} index {} using ($1/1000000):2 with lines title {}", quoted(&data), idx, quoted(name))
+            })
+            .collect();
+        let mut plot = vec![
+            format!("set title {}", quoted(output.label.as_deref().unwrap_or("evhz"))),
+            "set xlabel \"Elapsed (s)\"".to_string(),
+            format!("set ylabel \"Average ({})\"", output.unit.suffix()),
+            "set key outside".to_string(),
+        ];
+        if !series.is_empty() {
+            plot.push(format!("plot {}", series.join(", \\\n     ")));
+        }
+        std::fs::write(script, plot.join("\n") + "\n")
+    }
+}
+
+impl OutputSink for GnuplotSink {
+    fn on_sample(&mut self, output: &OutputConfig, sample: &HzSample) {
+        if sample.stats.avg_hz_exact > 0.0 {
+            self.record(&sample.stats.name, sample.time, &output.format_hz(sample.stats.avg_hz_exact));
+        }
+    }
+
+    fn on_close(&mut self, output: &OutputConfig) {
+        if let Err(err) = self.finish(output) {
+            log_event(Severity::Error, &format!("--gnuplot: failed to write the plot files: {}", err));
+        }
+    }
+}
+
+/// Opens every `--capture` file, ahead of its format version.
+const CAPTURE_MAGIC: &[u8; 8] = b"EVHZCAP\0";
+const CAPTURE_VERSION: u16 = 1;
+/// A record with this device index holds the high 32 bits of the next
+/// record's delta instead of an event.
+const CAPTURE_LONG_DELTA: u16 = u16::MAX;
+
+/// Writes a capture header: the magic, version, first event's time in
+/// nanoseconds, then the device names, each prefixed by its byte length.
+/// Integers are little-endian throughout.
+fn write_capture_header(out: &mut impl Write, started: Timestamp, names: &[String]) -> std::io::Result<()> {
+    out.write_all(CAPTURE_MAGIC)?;
+    out.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+    out.write_all(&started.0.to_le_bytes())?;
+    out.write_all(&(names.len() as u16).to_le_bytes())?;
+    for name in names {
+        let bytes = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+        out.write_all(&(bytes.len() as u16).to_le_bytes())?;
+        out.write_all(bytes)?;
+    }
+    Ok(())
+}
+
+/// Writes one 6-byte event record: the device index, then nanoseconds since
+/// that device's previous event, or since the capture started for its first.
+fn write_capture_record(out: &mut impl Write, idx: u16, delta: u64) -> std::io::Result<()> {
+    let high = (delta >> 32) as u32;
+    if high > 0 {
+        out.write_all(&CAPTURE_LONG_DELTA.to_le_bytes())?;
+        out.write_all(&high.to_le_bytes())?;
+    }
+    out.write_all(&idx.to_le_bytes())?;
+    out.write_all(&(delta as u32).to_le_bytes())
+}
+
+/// Records every event for `--capture` in the compact binary format above.
+/// Records are spooled to `<path>.part` as they come, and the header, only
+/// complete once every device has been seen, is put in front on exit.
+struct CaptureSink {
+    path: String,
+    spool: std::io::BufWriter<std::fs::File>,
+    names: Vec<String>,
+    /// Each device's last recorded time, by index.
+    last: Vec<Timestamp>,
+    started: Option<Timestamp>,
+}
+
+impl CaptureSink {
+    fn create(path: &str) -> std::io::Result<Self> {
+        let spool = std::fs::File::create(Self::spool_path(path))?;
+        Ok(Self {
+            path: path.to_string(),
+            spool: std::io::BufWriter::new(spool),
+            names: Vec::new(),
+            last: Vec::new(),
+            started: None,
+        })
+    }
+
+    fn spool_path(path: &str) -> String {
+        format!("{}.part", path)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.spool.flush()?;
+        let spool = Self::spool_path(&self.path);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&self.path)?);
+        write_capture_header(&mut out, self.started.unwrap_or(Timestamp(0)), &self.names)?;
+        std::io::copy(&mut std::fs::File::open(&spool)?, &mut out)?;
+        out.flush()?;
+        std::fs::remove_file(&spool)
+    }
+}
+
+impl OutputSink for CaptureSink {
+    fn on_event(&mut self, _output: &OutputConfig, device: &str, time: Timestamp) {
+        let started = *self.started.get_or_insert(time);
+        let idx = match self.names.iter().position(|seen| seen == device) {
+            Some(idx) => idx,
+            // The last index is taken by long deltas
+            None if self.names.len() < CAPTURE_LONG_DELTA as usize => {
+                self.names.push(device.to_string());
+                self.last.push(started);
+                self.names.len() - 1
+            }
+            None => return,
+        };
+        // A clock that stepped back is recorded as a zero interval
+        let delta = time.nanos_since(self.last[idx]);
+        self.last[idx] = Timestamp(self.last[idx].0 + delta);
+        // Disk errors surface when `finish` flushes the spool
+        let _ = write_capture_record(&mut self.spool, idx as u16, delta);
+    }
+
+    fn on_close(&mut self, _output: &OutputConfig) {
+        if let Err(err) = self.finish() {
+            log_event(Severity::Error, &format!("--capture: failed to write {}: {}", self.path, err));
+        }
+    }
+}
+
+/// Reads a `--capture` file back as events for `--replay`.
+struct CaptureReader<R> {
+    reader: R,
+    names: Vec<String>,
+    /// Each device's last event time, by index.
+    last: Vec<Timestamp>,
+}
+
+impl<R: std::io::Read> CaptureReader<R> {
+    /// Checks the magic and version and reads the device names.
+    fn new(mut reader: R) -> std::io::Result<Self> {
+        let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(invalid("not an evhz capture"));
+        }
+        let mut word = [0; 2];
+        reader.read_exact(&mut word)?;
+        let version = u16::from_le_bytes(word);
+        if version != CAPTURE_VERSION {
+            return Err(invalid(&format!("unsupported capture version {}", version)));
+        }
+        let mut started = [0; 8];
+        reader.read_exact(&mut started)?;
+        let started = Timestamp(u64::from_le_bytes(started));
+        reader.read_exact(&mut word)?;
+        let mut names = Vec::new();
+        for _ in 0..u16::from_le_bytes(word) {
+            reader.read_exact(&mut word)?;
+            let mut name = vec![0; u16::from_le_bytes(word) as usize];
+            reader.read_exact(&mut name)?;
+            names.push(String::from_utf8_lossy(&name).into_owned());
+        }
+        let last = vec![started; names.len()];
+        Ok(Self { reader, names, last })
+    }
+
+    /// The next event's time and device index, or `None` at end of file.
+    fn next_event(&mut self) -> std::io::Result<Option<(Timestamp, usize)>> {
+        let mut high = 0;
+        loop {
+            let mut record = [0; 6];
+            // A clean end falls between records; anything else is truncated
+            match self.reader.read(&mut record[..1])? {
+                0 if high == 0 => return Ok(None),
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                _ => self.reader.read_exact(&mut record[1..])?,
+            }
+            let idx = u16::from_le_bytes([record[0], record[1]]);
+            let low = u32::from_le_bytes([record[2], record[3], record[4], record[5]]);
+            if idx == CAPTURE_LONG_DELTA {
+                high = low;
+                continue;
+            }
+            let Some(last) = self.last.get_mut(idx as usize) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("record for unknown device {}", idx),
+                ));
+            };
+            *last = Timestamp(last.0 + ((high as u64) << 32 | low as u64));
+            return Ok(Some((*last, idx as usize)));
+        }
+    }
+}
+
+/// Per-event lines held back in a buffer and written out every `--flush-interval`
+/// instead of once per line, so high-rate runs cost fewer writes. Plain
+/// stdout already flushes every line, even into a pipe.
+struct BatchedStdout {
+    writer: std::io::BufWriter<std::io::Stdout>,
+}
+
+impl BatchedStdout {
+    /// Starts the thread that flushes `sink` every `every`, so a quiet device
+    /// doesn't leave its last lines waiting for the next event.
+    fn spawn(every: Duration) -> Arc<std::sync::Mutex<Self>> {
+        let sink = Arc::new(std::sync::Mutex::new(Self {
+            writer: std::io::BufWriter::with_capacity(64 * 1024, std::io::stdout()),
+        }));
+        let flushed = sink.clone();
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(every);
+                flushed.lock().unwrap().flush();
+            }
+        });
+        sink
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.writer, "{}", line);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// What to measure, as opposed to how it is shown.
+#[derive(Default)]
+struct CaptureConfig {
+    /// Read `timestamp_us device_name` lines from stdin instead of devices.
+    stdin: bool,
+    /// Read events back from this `--capture` file instead of devices.
+    replay: Option<std::path::PathBuf>,
+    /// Fabricate a device reporting at each of these rates instead.
+    simulate: Vec<u32>,
+    /// How the simulated intervals stray from their period.
+    simulate_jitter: SimulatedJitter,
+    /// Seeds the simulated jitter, picked from the clock when unset.
+    seed: Option<u64>,
+    /// End each run after this long instead of waiting for Ctrl-C.
+    duration: Option<Duration>,
+    /// End the session once no device has sent anything for this long.
+    idle_timeout: Option<Duration>,
+    /// How many runs of `duration` to make, reporting their spread.
+    runs: usize,
+    /// Read `/dev/hidraw*` reports instead of evdev events.
+    #[cfg(target_os = "linux")]
+    hidraw: bool,
+    /// Only count presses and repeats of this key, or of any keyboard key.
+    #[cfg(target_os = "linux")]
+    key: Option<KeyFilter>,
+    /// Skip virtual devices such as XTEST or uinput nodes.
+    #[cfg(target_os = "linux")]
+    no_virtual: bool,
+    /// Only count motion while this key or button is down.
+    #[cfg(target_os = "linux")]
+    while_held: Option<evdev::Key>,
+    /// Stop adding devices once this many passed the filters.
+    #[cfg(target_os = "linux")]
+    max_devices: Option<usize>,
+    /// Keep only the device with the most samples after `AUTO_SELECT_AFTER`.
+    #[cfg(target_os = "linux")]
+    auto_select: bool,
+    /// Also measure each device's reports split by how far they move.
+    #[cfg(target_os = "linux")]
+    speed_buckets: bool,
+    /// Also measure each multitouch slot's reports on its own.
+    #[cfg(target_os = "linux")]
+    per_slot: bool,
+    /// Also measure each mouse's X and Y motion on its own.
+    #[cfg(target_os = "linux")]
+    per_axis: bool,
+    /// Case-insensitive name fragments of devices to leave out.
+    #[cfg(target_os = "linux")]
+    exclude: Vec<String>,
+    /// Count nodes sharing a device name as one device.
+    #[cfg(target_os = "linux")]
+    merge_similar: bool,
+    /// Only count events while the focused X11 window has this class.
+    #[cfg(target_os = "linux")]
+    focus_window: Option<String>,
+    /// Dump each monitored device's evdev properties after the summary.
+    #[cfg(target_os = "linux")]
+    debug_devices: bool,
+    /// Look up each device's DPI through libratbag.
+    #[cfg(target_os = "linux")]
+    dpi: bool,
+    /// Only count an absolute axis event when the value moved by more than
+    /// this since the last one counted.
+    #[cfg(target_os = "linux")]
+    abs_threshold: Option<i32>,
+    /// Count `EV_FF` force-feedback events into their own entry per device.
+    #[cfg(target_os = "linux")]
+    count_ff: bool,
+    /// Count `EV_LED` events into their own entry per device.
+    #[cfg(target_os = "linux")]
+    count_led: bool,
+    /// Time how long buttons and keys are held, reported at exit.
+    #[cfg(target_os = "linux")]
+    click_timing: bool,
+    /// Monitor exactly these devices, skipping the filters above.
+    #[cfg(target_os = "linux")]
+    devices_file: Option<SavedDevices>,
+    /// Write the monitored devices here for a later `--devices-file`.
+    #[cfg(target_os = "linux")]
+    save_devices: Option<std::path::PathBuf>,
+    /// Exit successfully when there is no input to measure.
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    allow_no_devices: bool,
+}
+
+impl CaptureConfig {
+    /// Whether events come from `--stdin` or `--replay` rather than devices.
+    fn recorded(&self) -> bool {
+        self.stdin || self.replay.is_some()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureConfig {
+    /// Whether `--exclude` drops the device called `name`, noting it in the
+    /// verbose startup listing if so.
+    fn excludes(&self, output: &OutputConfig, node: &str, name: &str) -> bool {
+        let lower = name.to_lowercase();
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|pattern| lower.contains(&pattern.to_lowercase()));
+        if excluded && output.verbosity == Verbosity::Verbose {
+            println!("Excluding {}: {}", node, name);
+        }
+        excluded
+    }
+
+    /// Whether `kept` devices already reach `--max-devices`.
+    fn at_max_devices(&self, kept: usize) -> bool {
+        self.max_devices.is_some_and(|max| kept >= max)
+    }
+
+    /// Warns that `--max-devices` left `unopened` more nodes unopened.
+    fn warn_max_devices(&self, unopened: usize) {
+        if let Some(max) = self.max_devices
+            && unopened > 0
+        {
+            log_event(
+                Severity::Warning,
+                &format!("Reached --max-devices {}, leaving {} more node(s) unopened", max, unopened),
+            );
+        }
+    }
+}
+
+/// A device selection as `--save-devices` writes it: one `path<TAB>name`
+/// line per device.
+#[cfg(target_os = "linux")]
+struct SavedDevices(Vec<(String, String)>);
+
+#[cfg(target_os = "linux")]
+impl SavedDevices {
+    fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(path, name)| (path.to_string(), name.to_string()))
+            .collect();
+        SavedDevices(entries)
+    }
+
+    fn render(&self) -> String {
+        self.0.iter().map(|(path, name)| format!("{}\t{}\n", path, name)).collect()
+    }
+
+    /// Whether the device at `path` called `name` is in the selection, given
+    /// the names of every device found. Names match first so renumbered
+    /// nodes are still found; an entry whose name is gone falls back to its
+    /// path.
+    fn contains(&self, path: &str, name: &str, found: &[&str]) -> bool {
+        self.0.iter().any(|(saved_path, saved_name)| {
+            saved_name == name || (saved_path == path && !found.contains(&saved_name.as_str()))
+        })
+    }
+}
+
+/// How long one device's buttons and keys are held, for `--click-timing`.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct ClickTiming {
+    /// Press time of each key currently down.
+    pressed: std::collections::HashMap<u16, Timestamp>,
+    holds: u64,
+    total_nanos: u64,
+    min_nanos: u64,
+    max_nanos: u64,
+    /// Releases whose press wasn't seen, e.g. a button already down at start.
+    unmatched: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl ClickTiming {
+    fn press(&mut self, code: u16, time: Timestamp) {
+        self.pressed.entry(code).or_insert(time);
+    }
+
+    fn release(&mut self, code: u16, time: Timestamp) {
+        let Some(pressed) = self.pressed.remove(&code) else {
+            self.unmatched += 1;
+            return;
+        };
+        let nanos = time.nanos_since(pressed);
+        self.min_nanos = if self.holds == 0 { nanos } else { self.min_nanos.min(nanos) };
+        self.max_nanos = self.max_nanos.max(nanos);
+        self.total_nanos += nanos;
+        self.holds += 1;
+    }
+
+    fn print(&self, label: &str) {
+        if self.holds == 0 {
+            return;
+        }
+        let ms = |nanos: u64| nanos as f64 / 1_000_000.0;
+        print!(
+            "Clicks for {} {} held min {:.1}ms avg {:.1}ms max {:.1}ms",
+            label,
+            self.holds,
+            ms(self.min_nanos),
+            ms(self.total_nanos / self.holds),
+            ms(self.max_nanos)
+        );
+        if self.unmatched > 0 {
+            print!(" ({} releases without a press)", self.unmatched);
+        }
+        println!();
+    }
+}
+
+/// Parses an evdev key given either by name (`KEY_SPACE`) or numeric code.
+#[cfg(target_os = "linux")]
+fn parse_key(value: &str) -> Option<evdev::Key> {
+    match value.parse::<u16>() {
+        Ok(code) => Some(evdev::Key::new(code)),
+        Err(_) => value.to_uppercase().parse().ok(),
+    }
+}
+
+/// The presses `--key` counts: one key, or with `any` every keyboard key.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyFilter {
+    One(evdev::Key),
+    /// Codes below `BTN_0`, leaving out mouse, joystick and pen buttons.
+    Any,
+}
+
+#[cfg(target_os = "linux")]
+impl KeyFilter {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            _ if value.eq_ignore_ascii_case("any") => Some(KeyFilter::Any),
+            _ => parse_key(value).map(KeyFilter::One),
+        }
+    }
+
+    fn matches(self, key: evdev::Key) -> bool {
+        match self {
+            KeyFilter::One(wanted) => key == wanted,
+            KeyFilter::Any => (1..evdev::Key::BTN_0.code()).contains(&key.code()),
+        }
+    }
+
+    /// Whether a device with `keys` can send a press this counts.
+    fn supported_by(self, keys: &evdev::AttributeSetRef<evdev::Key>) -> bool {
+        match self {
+            KeyFilter::One(key) => keys.contains(key),
+            KeyFilter::Any => keys.iter().any(|key| self.matches(key)),
+        }
+    }
+}
+
+/// Current wall-clock time as an ISO 8601 UTC timestamp with milliseconds.
+fn now_iso8601() -> String {
+    iso8601(SystemTime::now())
+}
+
+/// Formats `time` as `YYYY-MM-DDTHH:MM:SS.mmmZ`. Timestamps are always UTC so
+/// every output agrees regardless of the machine's timezone.
+fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// How long the measurement loop may go without a heartbeat before the
+/// watchdog warns. Loops wake at least every 100ms even when idle.
+const STALL_AFTER: Duration = Duration::from_secs(5);
+
+/// Flags flipped from other threads (Ctrl-C handler, stdin reader) that the
+/// measurement loops poll.
+#[derive(Clone)]
+struct Controls {
+    running: Arc<AtomicBool>,
+    /// Set by Ctrl-C, which also ends the current run: no further `--runs`.
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    /// Set while `--focus-window`'s window isn't focused; counts as paused.
+    unfocused: Arc<AtomicBool>,
+    /// `Timestamp` of the measurement loop's latest iteration.
+    heartbeat: Arc<AtomicU64>,
+}
+
+impl Controls {
+    fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(true)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            unfocused: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(AtomicU64::new(Timestamp::now().0)),
+        }
+    }
+
+    fn running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Lets the next `--runs` pass start measuring.
+    fn restart(&self) {
+        self.beat();
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    /// Ends the current run once `duration` has passed.
+    fn stop_after(&self, duration: Duration) {
+        let running = self.running.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            running.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Ends the session, as Ctrl-C would, once `last_event` is `timeout`
+    /// old. Time spent paused or unfocused doesn't count as idle.
+    fn stop_when_idle(&self, timeout: Duration, last_event: Arc<AtomicU64>) {
+        let controls = self.clone();
+        std::thread::spawn(move || {
+            let tick = (timeout / 10).min(Duration::from_millis(100));
+            while !controls.cancelled() {
+                std::thread::sleep(tick);
+                if let Some(idle) = controls.idle_for(timeout, &last_event, Timestamp::now()) {
+                    log_event(Severity::Notice, &format!("No events for {:.1}s, stopping", idle.as_secs_f64()));
+                    controls.cancelled.store(true, Ordering::SeqCst);
+                    controls.running.store(false, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
+    /// How long the session has gone without events at `now`, once that
+    /// reaches `timeout`. While paused or unfocused it moves `last_event`
+    /// up to `now` instead.
+    fn idle_for(&self, timeout: Duration, last_event: &AtomicU64, now: Timestamp) -> Option<Duration> {
+        if self.paused.load(Ordering::SeqCst) || self.unfocused.load(Ordering::SeqCst) {
+            last_event.fetch_max(now.0, Ordering::Relaxed);
+            return None;
+        }
+        let idle = Duration::from_nanos(now.nanos_since(Timestamp(last_event.load(Ordering::Relaxed))));
+        (idle >= timeout).then_some(idle)
+    }
+
+    /// Marks the measurement loop alive; called once per iteration.
+    fn beat(&self) {
+        self.heartbeat.store(Timestamp::now().0, Ordering::Relaxed);
+    }
+
+    /// Warns on stderr when the measurement loop stops beating for
+    /// `STALL_AFTER`, which points at a hung driver read rather than an
+    /// idle device, and again once it recovers.
+    fn spawn_watchdog(&self) {
+        let controls = self.clone();
+        std::thread::spawn(move || {
+            let mut stalled = false;
+            while !controls.cancelled() {
+                std::thread::sleep(Duration::from_secs(1));
+                // Between `--runs` passes nothing is measured
+                if !controls.running() {
+                    continue;
+                }
+                let last = Timestamp(controls.heartbeat.load(Ordering::Relaxed));
+                let silent = Duration::from_nanos(Timestamp::now().nanos_since(last));
+                if silent >= STALL_AFTER && !stalled {
+                    log_event(
+                        Severity::Warning,
+                        &format!("Measurement loop stalled for {}s, a device read may be hung", silent.as_secs()),
+                    );
+                } else if silent < STALL_AFTER && stalled {
+                    log_event(Severity::Notice, "Measurement loop recovered");
+                }
+                stalled = silent >= STALL_AFTER;
+            }
+        });
+    }
+
+    /// Keeps `unfocused` up to date from the focused X11 window, checked
+    /// every `FOCUS_POLL`. A window matches when its `WM_CLASS` instance or
+    /// class name equals `class`, ignoring case.
+    #[cfg(target_os = "linux")]
+    fn spawn_focus_watch(&self, query: x11::FocusQuery, class: String, verbosity: Verbosity) {
+        let matches = move |query: &x11::FocusQuery| {
+            query.active_class().is_some_and(|(instance, window_class)| {
+                instance.eq_ignore_ascii_case(&class) || window_class.eq_ignore_ascii_case(&class)
+            })
+        };
+        // Settled before the first event, not a poll later
+        self.unfocused.store(!matches(&query), Ordering::SeqCst);
+        let controls = self.clone();
+        std::thread::spawn(move || {
+            while !controls.cancelled() {
+                std::thread::sleep(FOCUS_POLL);
+                let unfocused = !matches(&query);
+                if controls.unfocused.swap(unfocused, Ordering::SeqCst) != unfocused
+                    && verbosity > Verbosity::Quiet
+                {
+                    let state = if unfocused { "focus lost" } else { "focus gained" };
+                    println!("{} {}", state, now_iso8601());
+                }
+            }
+        });
+    }
+
+    /// Toggles the pause flag whenever a `p` is read from stdin.
+    fn spawn_pause_reader(&self, verbosity: Verbosity) {
+        let paused = self.paused.clone();
+        std::thread::spawn(move || {
+            use std::io::Read;
+            for byte in std::io::stdin().lock().bytes() {
+                let Ok(byte) = byte else { break };
+                if byte.eq_ignore_ascii_case(&b'p') {
+                    let now_paused = !paused.fetch_xor(true, Ordering::SeqCst);
+                    if verbosity > Verbosity::Quiet {
+                        let state = if now_paused { "paused" } else { "resumed" };
+                        println!("{} {}", state, now_iso8601());
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// How serious a runtime message is, mapped to a syslog level with `--syslog`.
+#[derive(Clone, Copy)]
+enum Severity {
+    Error,
+    Warning,
+    Notice,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Info,
+}
+
+/// Whether `--syslog` routes runtime messages to syslog. Global because the
+/// watchdog thread and fatal errors have no `OutputConfig` to hand.
+#[cfg(unix)]
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn open_syslog() {
+    unsafe {
+        libc::openlog(c"evhz".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON);
+    }
+    SYSLOG.store(true, Ordering::SeqCst);
+}
+
+/// Sends `message` to syslog if `--syslog` is on, returning whether it did.
+#[cfg(unix)]
+fn to_syslog(severity: Severity, message: &str) -> bool {
+    if !SYSLOG.load(Ordering::SeqCst) {
+        return false;
+    }
+    let priority = match severity {
+        Severity::Error => libc::LOG_ERR,
+        Severity::Warning => libc::LOG_WARNING,
+        Severity::Notice => libc::LOG_NOTICE,
+        Severity::Info => libc::LOG_INFO,
+    };
+    // Interior NULs would cut the message short, so they can't reach libc
+    let message = std::ffi::CString::new(message.replace('\0', " ")).unwrap_or_default();
+    unsafe {
+        libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn to_syslog(_: Severity, _: &str) -> bool {
+    false
+}
+
+/// Reports a runtime event while measuring: to syslog with `--syslog`,
+/// otherwise on stderr behind a timestamp.
+fn log_event(severity: Severity, message: &str) {
+    if !to_syslog(severity, message) {
+        eprintln!("{} {}", now_iso8601(), message);
+    }
+}
+
+/// Set by the SIGUSR1 handler and cleared by the measurement loop that
+/// prints the snapshot. A signal handler can't reach `Controls`, so this
+/// one flag is global.
+#[cfg(unix)]
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGUSR1 request a snapshot instead of terminating the process.
+#[cfg(unix)]
+fn install_snapshot_handler() {
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        SNAPSHOT_REQUESTED.store(true, Ordering::SeqCst);
+    }
+    unsafe {
+        libc::signal(libc::SIGUSR1, on_sigusr1 as *const () as libc::sighandler_t);
+    }
+}
+
+/// Prints every device's exit report, headed by the time, if SIGUSR1
+/// arrived since the last call. Loops call it between event batches, so no
+/// update is half done.
+#[cfg(unix)]
+fn snapshot_if_requested<'a>(output: &OutputConfig, stats: impl Iterator<Item = &'a DeviceStats>) {
+    if !SNAPSHOT_REQUESTED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    output.flush_events();
+    println!("{} Snapshot", now_iso8601());
+    for stats in stats {
+        stats.print_average(output, None);
+    }
+    let _ = std::io::stdout().flush();
+}
+
+/// Follows `Controls::paused` and `unfocused` from inside a measurement loop.
+#[derive(Default)]
+struct PauseWatch {
+    paused: bool,
+}
+
+impl PauseWatch {
+    /// Returns whether counting is paused. Whenever the state flips every
+    /// device forgets its previous event, so the pause gap isn't measured.
+    fn check<'a>(
+        &mut self,
+        controls: &Controls,
+        stats: impl Iterator<Item = &'a mut DeviceStats>,
+    ) -> bool {
+        let paused = controls.paused.load(Ordering::SeqCst) || controls.unfocused.load(Ordering::SeqCst);
+        if paused != self.paused {
+            for s in stats {
+                s.prev_time = None;
+            }
+            self.paused = paused;
+        }
+        paused
+    }
+}
+
+/// A reading of the measurement clock, in nanoseconds. On Linux this is
+/// `CLOCK_MONOTONIC_RAW`, which NTP never slews, on Windows the raw
+/// `QueryPerformanceCounter` ticks; elsewhere it's `Instant`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Timestamp(u64);
+
+impl Timestamp {
+    fn now() -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+            if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts) } == 0 {
+                return Timestamp(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64);
+            }
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+            static FREQUENCY: std::sync::OnceLock<i64> = std::sync::OnceLock::new();
+            let frequency = *FREQUENCY.get_or_init(|| {
+                let mut frequency = 0;
+                unsafe { QueryPerformanceFrequency(&mut frequency) }.map_or(0, |_| frequency)
+            });
+            let mut ticks = 0;
+            if frequency > 0 && unsafe { QueryPerformanceCounter(&mut ticks) }.is_ok() {
+                return Timestamp((ticks as u128 * 1_000_000_000 / frequency as u128) as u64);
+            }
+        }
+
+        static BASE: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+        Timestamp(BASE.get_or_init(Instant::now).elapsed().as_nanos() as u64)
+    }
+
+    /// This time moved back by `duration`, saturating at the clock's origin.
+    fn minus(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_sub(duration.as_nanos() as u64))
+    }
+
+    fn nanos_since(self, earlier: Timestamp) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl std::ops::AddAssign<Duration> for Timestamp {
+    fn add_assign(&mut self, duration: Duration) {
+        self.0 += duration.as_nanos() as u64;
+    }
+}
+
+/// The samples of a median window as value counts in a lower and an upper
+/// half, so each sample costs an insert, an eviction and a rebalance in
+/// O(log n) however large the window.
+#[derive(Default)]
+struct MedianWindow {
+    /// Never shorter than `high`, nor longer by more than one sample.
+    low: std::collections::BTreeMap<u32, usize>,
+    high: std::collections::BTreeMap<u32, usize>,
+    low_len: usize,
+    high_len: usize,
+}
+
+impl MedianWindow {
+    fn add(half: &mut std::collections::BTreeMap<u32, usize>, hz: u32) {
+        *half.entry(hz).or_default() += 1;
+    }
+
+    fn take(half: &mut std::collections::BTreeMap<u32, usize>, hz: u32) {
+        if let Some(count) = half.get_mut(&hz) {
+            *count -= 1;
+            if *count == 0 {
+                half.remove(&hz);
+            }
+        }
+    }
+
+    fn insert(&mut self, hz: u32) {
+        if self.low.last_key_value().is_none_or(|(&max, _)| hz <= max) {
+            Self::add(&mut self.low, hz);
+            self.low_len += 1;
+        } else {
+            Self::add(&mut self.high, hz);
+            self.high_len += 1;
+        }
+        self.rebalance();
+    }
+
+    /// Drops one sample of `hz`, which must be in the window.
+    fn remove(&mut self, hz: u32) {
+        if self.low.last_key_value().is_some_and(|(&max, _)| hz <= max) {
+            Self::take(&mut self.low, hz);
+            self.low_len -= 1;
+        } else {
+            Self::take(&mut self.high, hz);
+            self.high_len -= 1;
+        }
+        self.rebalance();
+    }
+
+    fn rebalance(&mut self) {
+        while self.low_len > self.high_len + 1
+            && let Some((&max, _)) = self.low.last_key_value()
+        {
+            Self::take(&mut self.low, max);
+            Self::add(&mut self.high, max);
+            self.low_len -= 1;
+            self.high_len += 1;
+        }
+        while self.high_len > self.low_len
+            && let Some((&min, _)) = self.high.first_key_value()
+        {
+            Self::take(&mut self.high, min);
+            Self::add(&mut self.low, min);
+            self.high_len -= 1;
+            self.low_len += 1;
+        }
+    }
+
+    fn median(&self) -> Option<f64> {
+        let (&low, _) = self.low.last_key_value()?;
+        match self.high.first_key_value() {
+            Some((&high, _)) if self.low_len == self.high_len => Some((low as f64 + high as f64) / 2.0),
+            _ => Some(low as f64),
+        }
+    }
+}
+
+struct DeviceStats {
+    name: String,
+    name_width: usize,
+    hz_history: VecDeque<u32>,
+    /// Running sum of `hz_history` so the average stays O(1) per event.
+    hz_sum: u64,
+    /// `hz_history` in sorted halves, with median smoothing.
+    median: MedianWindow,
+    /// When each `hz_history` sample was taken, with `--window-ms`.
+    hz_times: VecDeque<Timestamp>,
+    /// Nanoseconds between each pair of adjacent intervals in `hz_history`,
+    /// their running sum, and the latest interval.
+    jitter: VecDeque<u32>,
+    jitter_sum: u64,
+    last_interval: Option<u64>,
+    avg_hz: u32,
+    /// `avg_hz` before truncating to whole Hz.
+    avg_hz_exact: f64,
+    /// The smoothing strategy's running value, kept even while
+    /// `--min-samples` holds the reported average at zero.
+    smoothed: f64,
+    /// Average of the very first full window, the baseline for `trend`.
+    first_avg_hz: Option<u32>,
+    /// Latest raw intervals in nanoseconds, including ones the Hz filter
+    /// rejects. Only kept with `--analyze`.
+    intervals: VecDeque<u64>,
+    /// Latest `--tail` raw intervals in nanoseconds, filtered or not.
+    tail: VecDeque<u64>,
+    /// Event times within the last `RATE_WINDOW`, with `--windowed`. Bounded
+    /// by `MAX_HZ`.
+    recent: VecDeque<Timestamp>,
+    /// Session-wide interval counts per `GAP_BUCKETS` range, with `--gaps`.
+    gap_counts: [u64; 4],
+    prev_time: Option<Timestamp>,
+    /// Polling rate the device's USB endpoint asks for, when known.
+    declared_hz: Option<u32>,
+    /// Sensor resolution libratbag reports, with `--dpi`.
+    dpi: Option<u32>,
+    /// Session-wide sample count, extremes and Welford running mean and
+    /// squared deviations, unaffected by the smoothing window.
+    count: u64,
+    min_hz: u32,
+    max_hz: u32,
+    mean_hz: f64,
+    m2_hz: f64,
+    /// Intervals over a second and timestamps earlier than the previous
+    /// one, left out of every average.
+    long_gaps: u64,
+    backwards: u64,
+    /// Intervals the Hz filter rejected, long gaps included.
+    rejected: u64,
+    /// The `--count-rate` second in progress and its events so far, the
+    /// count of the last complete one, and totals over complete seconds.
+    second_start: Option<Timestamp>,
+    second_events: u64,
+    counted_hz: Option<u64>,
+    counted_events: u64,
+    counted_seconds: u64,
+}
+
+impl DeviceStats {
+    fn new(name: String) -> Self {
+        Self {
+            name_width: name.chars().count(),
+            name,
+            hz_history: VecDeque::with_capacity(HZ_LIST),
+            hz_sum: 0,
+            median: MedianWindow::default(),
+            hz_times: VecDeque::new(),
+            jitter: VecDeque::new(),
+            jitter_sum: 0,
+            last_interval: None,
+            avg_hz: 0,
+            avg_hz_exact: 0.0,
+            smoothed: 0.0,
+            first_avg_hz: None,
+            intervals: VecDeque::new(),
+            tail: VecDeque::new(),
+            recent: VecDeque::new(),
+            gap_counts: [0; 4],
+            prev_time: None,
+            declared_hz: None,
+            dpi: None,
+            count: 0,
+            min_hz: u32::MAX,
+            max_hz: 0,
+            mean_hz: 0.0,
+            m2_hz: 0.0,
+            long_gaps: 0,
+            backwards: 0,
+            rejected: 0,
+            second_start: None,
+            second_events: 0,
+            counted_hz: None,
+            counted_events: 0,
+            counted_seconds: 0,
+        }
+    }
+
+    /// Records an event that happens right now.
+    #[cfg(not(target_os = "windows"))]
+    fn update_now(&mut self, output: &OutputConfig, sinks: &mut Sinks) {
+        self.update(Timestamp::now(), output, sinks);
+    }
+
+    /// Counts an event into its second for `--count-rate`. Seconds start at
+    /// the device's first event; the first event past one closes it.
+    fn count_second(&mut self, time: Timestamp) {
+        let start = *self.second_start.get_or_insert(time);
+        let elapsed = time.nanos_since(start) / 1_000_000_000;
+        if elapsed > 0 {
+            // Later seconds skipped over had no events at all
+            self.counted_hz = Some(if elapsed == 1 { self.second_events } else { 0 });
+            self.counted_events += self.second_events;
+            self.counted_seconds += 1;
+            self.second_start = Some(Timestamp(start.0 + elapsed * 1_000_000_000));
+            self.second_events = 0;
+        }
+        self.second_events += 1;
+    }
+
+    /// Events per second over the complete seconds a device sent anything in.
+    fn counted_average(&self) -> Option<f64> {
+        (self.counted_seconds > 0).then(|| self.counted_events as f64 / self.counted_seconds as f64)
+    }
+
+    /// Records an event that happened at `time`, handing it and any sample
+    /// it yields to `sinks`.
+    fn update(&mut self, time: Timestamp, output: &OutputConfig, sinks: &mut Sinks) {
+        if let Some(last_event) = &output.last_event {
+            last_event.fetch_max(time.0, Ordering::Relaxed);
+        }
+        sinks.event(output, &self.name, time);
+        if output.count_rate {
+            self.count_second(time);
+        }
+        if output.windowed {
+            let cutoff = time.minus(RATE_WINDOW);
+            while self.recent.front().is_some_and(|&oldest| oldest <= cutoff) {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(time);
+        }
+
+        if let Some(prev) = self.prev_time
+            && time < prev
+        {
+            // A clock that stepped back leaves no interval to measure, so
+            // counting restarts from this event
+            self.backwards += 1;
+            self.last_interval = None;
+        } else if let Some(prev) = self.prev_time {
+            let nanos = time.nanos_since(prev).saturating_sub(output.overhead_ns);
+            let micros = nanos / 1000;
+            // Idle, or a suspended VM or scheduler stall; the Hz filter below
+            // keeps it out of the average either way
+            if nanos > 1_000_000_000 {
+                self.long_gaps += 1;
+            }
+
+            if output.analyze {
+                if self.intervals.len() >= HZ_LIST {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back(nanos);
+            }
+            if output.tail > 0 {
+                if self.tail.len() >= output.tail {
+                    self.tail.pop_front();
+                }
+                self.tail.push_back(nanos);
+            }
+            if output.gaps {
+                let bucket = GAP_BUCKETS.iter().take_while(|&&bound| micros >= bound).count();
+                self.gap_counts[bucket] += 1;
+            }
+
+            // Compared at full width: a 0.5µs interval is 2MHz and must
+            // reach the cap as such rather than wrap or truncate
+            match 1_000_000_000u64.checked_div(nanos) {
+                Some(hz) if hz > 0 && hz < MAX_HZ => {
+                    let hz = hz as u32;
+                    let window = output.smoothing.window();
+                    let median = matches!(output.smoothing, Smoothing::Median { .. });
+                    let full = match output.window_span {
+                        // Full once the samples span the whole window
+                        Some(span) => {
+                            let cutoff = time.minus(span);
+                            let mut evicted = false;
+                            while self.hz_times.front().is_some_and(|&oldest| oldest <= cutoff)
+                                && let Some(oldest) = self.hz_history.pop_front()
+                            {
+                                self.hz_times.pop_front();
+                                self.hz_sum -= oldest as u64;
+                                if median {
+                                    self.median.remove(oldest);
+                                }
+                                evicted = true;
+                            }
+                            self.hz_times.push_back(time);
+                            evicted || self.first_avg_hz.is_some()
+                        }
+                        None => {
+                            while self.hz_history.len() >= window
+                                && let Some(oldest) = self.hz_history.pop_front()
+                            {
+                                self.hz_sum -= oldest as u64;
+                                if median {
+                                    self.median.remove(oldest);
+                                }
+                            }
+                            self.hz_history.len() + 1 == window
+                        }
+                    };
+                    self.hz_history.push_back(hz);
+                    self.hz_sum += hz as u64;
+                    if median {
+                        self.median.insert(hz);
+                    }
+
+                    // Intervals are under a second here, so differences fit a u32
+                    if let Some(last) = self.last_interval {
+                        let diff = nanos.abs_diff(last) as u32;
+                        self.jitter.push_back(diff);
+                        self.jitter_sum += diff as u64;
+                    }
+                    self.last_interval = Some(nanos);
+                    while self.jitter.len() >= self.hz_history.len()
+                        && let Some(oldest) = self.jitter.pop_front()
+                    {
+                        self.jitter_sum -= oldest as u64;
+                    }
+
+                    self.count += 1;
+                    self.min_hz = self.min_hz.min(hz);
+                    self.max_hz = self.max_hz.max(hz);
+                    let delta = hz as f64 - self.mean_hz;
+                    self.mean_hz += delta / self.count as f64;
+                    self.m2_hz += delta * (hz as f64 - self.mean_hz);
+
+                    self.smoothed = match output.smoothing {
+                        Smoothing::Mean { .. } => self.hz_sum as f64 / self.hz_history.len() as f64,
+                        Smoothing::Median { .. } => self.median.median().unwrap_or(hz as f64),
+                        Smoothing::Ewma { alpha } if self.hz_history.len() > 1 => {
+                            alpha * hz as f64 + (1.0 - alpha) * self.smoothed
+                        }
+                        Smoothing::Ewma { .. } => hz as f64,
+                    };
+                    // A full window always reports, or a large threshold never would
+                    let measuring = !full && self.hz_history.len() < output.min_samples;
+                    self.avg_hz_exact = if measuring { 0.0 } else { self.smoothed };
+                    self.avg_hz = output.rounding.apply(self.avg_hz_exact) as u32;
+                    let latest = 1_000_000_000.0 / nanos as f64;
+
+                    if self.first_avg_hz.is_none() && full {
+                        self.first_avg_hz = Some(self.avg_hz);
+                    }
+                    let sample = HzSample {
+                        stats: self,
+                        time,
+                        hz: latest,
+                    };
+                    sinks.sample(output, &sample);
+                }
+                _ => {
+                    self.rejected += 1;
+                    // Jitter compares adjacent intervals, not ones either side of a gap
+                    self.last_interval = None;
+                }
+            }
+        }
+
+        self.prev_time = Some(time);
+    }
+
+    /// The current average as a percentage of the session peak, the fastest
+    /// single interval so far, which the average can only approach.
+    fn peak_pct(&self) -> Option<f64> {
+        (self.max_hz > 0 && self.avg_hz_exact > 0.0).then(|| self.avg_hz_exact * 100.0 / self.max_hz as f64)
+    }
+
+    /// Prints the exit report, comparing the average to `previous` from
+    /// `--compare-previous` when the last run measured this device.
+    fn print_average(&self, output: &OutputConfig, previous: Option<f64>) {
+        let unit = output.unit.suffix();
+        if self.avg_hz > 0 {
+            let average = output.padded_hz(self.avg_hz_exact);
+            let mut line = match self.trend() {
+                Some((arrow, delta)) => format!(
+                    "Average for {} {}{} {} {}{}",
+                    self.label(),
+                    average,
+                    unit,
+                    arrow,
+                    output.signed_hz(delta as f64),
+                    unit
+                ),
+                None => format!("Average for {} {}{}", self.label(), average, unit),
+            };
+            if output.count_rate
+                && let Some(counted) = self.counted_average()
+            {
+                line += &format!(" (counted {}{})", output.format_hz(counted), unit);
+            }
+            if let Some(previous) = previous {
+                let delta = self.avg_hz_exact - previous;
+                line += &format!(
+                    " ({}{} vs last run)",
+                    output.signed_hz(delta),
+                    unit
+                );
+            }
+            println!("{}", line);
+        } else if self.count == 0 && self.rejected + self.backwards > 0 {
+            // Otherwise indistinguishable from a device that stayed idle
+            println!(
+                "Average for {} no valid samples ({} discarded)",
+                self.label(),
+                self.rejected + self.backwards
+            );
+        }
+        self.print_details(output);
+    }
+
+    /// The exit report's `--gaps`, `--tail`, `--mode-rate` and `--analyze`
+    /// lines.
+    fn print_details(&self, output: &OutputConfig) {
+        let unit = output.unit.suffix();
+        let total: u64 = self.gap_counts.iter().sum();
+        if output.gaps && total > 0 {
+            let split: Vec<String> = GAP_LABELS
+                .iter()
+                .zip(self.gap_counts)
+                .map(|(label, count)| format!("{} {:5.1}%", label, count as f64 * 100.0 / total as f64))
+                .collect();
+            println!("Gaps for {} {}", self.label(), split.join("  "));
+        }
+
+        if !self.tail.is_empty() {
+            println!("Tail for {} last {} intervals, oldest first:", self.label(), self.tail.len());
+            for &nanos in &self.tail {
+                let hz = if nanos > 0 { 1_000_000_000.0 / nanos as f64 } else { 0.0 };
+                println!("  {:10.3}µs {}{}", nanos as f64 / 1000.0, output.padded_hz(hz), unit);
+            }
+        }
+
+        if output.mode_rate
+            && let Some((hz, share)) = self.dominant_hz()
+        {
+            println!(
+                "Mode for {} dominant: {}{} ({:.0}% of samples)",
+                self.label(),
+                output.format_hz(hz as f64),
+                unit,
+                share * 100.0
+            );
+        }
+
+        if output.analyze && self.intervals.len() >= HZ_LIST / 4 {
+            match self.double_reporting_hz() {
+                Some(hz) => println!(
+                    "Analysis for {} possible double-reporting — effective rate may be half (~{}{})",
+                    self.label(),
+                    output.format_hz(hz),
+                    unit
+                ),
+                None => println!("Analysis for {} no double-reporting pattern", self.label()),
+            }
+        }
+        if output.analyze && self.long_gaps + self.backwards > 0 {
+            println!(
+                "Skipped for {} {} interval(s) over 1s, {} backwards timestamp(s)",
+                self.label(),
+                self.long_gaps,
+                self.backwards
+            );
+        }
+    }
+
+    /// Mean absolute difference between adjacent intervals of the retained
+    /// window, in microseconds.
+    fn jitter_us(&self) -> Option<f64> {
+        (!self.jitter.is_empty()).then(|| self.jitter_sum as f64 / self.jitter.len() as f64 / 1000.0)
+    }
+
+    /// Nearest-rank percentile of the retained window.
+    fn percentile_hz(&self, pct: usize) -> Option<u32> {
+        let mut sorted: Vec<u32> = self.hz_history.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (sorted.len() * pct).div_ceil(100).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    /// The declared rate when the session average is more than
+    /// `OVER_DECLARED` above it, which no honest report stream can be.
+    fn over_declared(&self) -> Option<u32> {
+        let declared = self.declared_hz?;
+        (self.count > 0 && self.mean_hz > declared as f64 * OVER_DECLARED).then_some(declared)
+    }
+
+    /// Looks for devices that send every update twice: intervals split into
+    /// a near-zero cluster and a regular one of roughly equal size. Returns
+    /// the rate implied by the regular cluster alone.
+    fn double_reporting_hz(&self) -> Option<f64> {
+        let mut sorted: Vec<u64> = self.intervals.iter().copied().collect();
+        sorted.sort_unstable();
+
+        // With half the intervals near zero the median is meaningless, so
+        // the regular cadence is read from the upper quartile instead
+        let regular = sorted[sorted.len() * 3 / 4];
+        if regular == 0 {
+            return None;
+        }
+        let short = sorted.iter().filter(|&&nanos| nanos * 4 < regular).count();
+
+        let fraction = short as f64 / sorted.len() as f64;
+        (0.35..=0.65)
+            .contains(&fraction)
+            .then(|| 1_000_000_000.0 / regular as f64)
+    }
+
+    /// Figure the live displays show: events in the trailing `RATE_WINDOW`
+    /// with `--windowed`, otherwise the interval average after `--trim`.
+    /// Recorded input has its own clock, so there the window ends at the
+    /// device's latest event.
+    fn live_hz(&self, output: &OutputConfig) -> f64 {
+        if !output.windowed {
+            return if self.avg_hz > 0 { output.trim.average(self) } else { 0.0 };
+        }
+        let end = match self.recent.back() {
+            Some(&latest) if output.recorded => latest,
+            _ => Timestamp::now(),
+        };
+        let cutoff = end.minus(RATE_WINDOW);
+        let count = self.recent.iter().rev().take_while(|&&time| time > cutoff).count();
+        count as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    /// Most frequent rate among the retained samples and the share of them
+    /// it covers. Samples within 10% of a standard rate count as that rate,
+    /// others are bucketed to the nearest 10Hz.
+    fn dominant_hz(&self) -> Option<(u32, f64)> {
+        let mut counts = std::collections::BTreeMap::new();
+        for &hz in &self.hz_history {
+            let bucket = STANDARD_RATES
+                .iter()
+                .copied()
+                .find(|&rate| hz.abs_diff(rate) * 10 <= rate)
+                .unwrap_or((hz + 5) / 10 * 10);
+            *counts.entry(bucket).or_insert(0usize) += 1;
+        }
+        let (hz, count) = counts.into_iter().max_by_key(|&(_, count)| count)?;
+        Some((hz, count as f64 / self.hz_history.len() as f64))
+    }
+
+    /// Summary fields from `trend_hz` on. `DeviceSummary` writes the ones
+    /// from the name to `jitter_us` itself.
+    fn json_fields(&self, output: &OutputConfig) -> Vec<(&'static str, Json)> {
+        let mut fields = vec![(
+            "trend_hz",
+            self.trend()
+                .map_or(Json::Null, |(_, delta)| Json::hz(output, delta as f64)),
+        )];
+        if output.gaps {
+            let counts = GAP_LABELS
+                .iter()
+                .zip(self.gap_counts)
+                .map(|(label, count)| Json::Object(vec![
+                    ("range", Json::String(label.to_string())),
+                    ("count", Json::Number(count.to_string())),
+                ]))
+                .collect();
+            fields.push(("gaps", Json::Array(counts)));
+        }
+        if output.tail > 0 {
+            let tail = self
+                .tail
+                .iter()
+                .map(|&nanos| Json::Number(format!("{:.3}", nanos as f64 / 1000.0)))
+                .collect();
+            fields.push(("tail_us", Json::Array(tail)));
+        }
+        if output.count_rate {
+            fields.push(("counted_hz", self.counted_average().map_or(Json::Null, |hz| Json::hz(output, hz))));
+        }
+        if output.mode_rate {
+            let dominant = self.dominant_hz();
+            fields.push(("dominant_hz", dominant.map_or(Json::Null, |(hz, _)| Json::hz(output, hz as f64))));
+            fields.push((
+                "dominant_share",
+                dominant.map_or(Json::Null, |(_, share)| Json::Number(format!("{:.3}", share))),
+            ));
+        }
+        if output.analyze {
+            let hz = self.double_reporting_hz().filter(|_| self.intervals.len() >= HZ_LIST / 4);
+            fields.push((
+                "double_reporting_hz",
+                hz.map_or(Json::Null, |hz| Json::hz(output, hz)),
+            ));
+            fields.push(("long_gaps", Json::Number(self.long_gaps.to_string())));
+            fields.push(("backwards_timestamps", Json::Number(self.backwards.to_string())));
+        }
+        fields
+    }
+
+
+    /// How the latest window compares to the first one: an arrow and the
+    /// difference in Hz. Changes within 1% of the first average count as flat.
+    fn trend(&self) -> Option<(char, i64)> {
+        let first = self.first_avg_hz?;
+        let delta = self.avg_hz as i64 - first as i64;
+        let arrow = if delta.abs() * 100 <= first as i64 {
+            '→'
+        } else if delta > 0 {
+            '↑'
+        } else {
+            '↓'
+        };
+        Some((arrow, delta))
+    }
+
+    /// Separate stats for one kind of a device's events, such as its scroll
+    /// wheel, padded like the device itself.
+    #[cfg(target_os = "linux")]
+    fn companion(&self, kind: &str) -> Self {
+        let mut companion = DeviceStats::new(format!("{} {}", self.name, kind));
+        companion.name_width = companion.name_width.max(self.name_width);
+        companion
+    }
+
+    /// The name as shown beside rates, with the DPI when it is known.
+    fn display_name(&self) -> String {
+        match self.dpi {
+            Some(dpi) => format!("{} ({} DPI)", self.name, dpi),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Device name with its colon, padded so columns line up across devices.
+    fn label(&self) -> String {
+        format!("{:<width$}", format!("{}:", self.display_name()), width = self.name_width + 1)
+    }
+}
+
+/// Minimal JSON value for the structured outputs; there is no serde
+/// dependency, and numbers are kept preformatted so integers stay integers.
+enum Json {
+    Null,
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn hz(output: &OutputConfig, hz: f64) -> Self {
+        Json::Number(output.format_hz(hz))
+    }
+
+    /// Serializes the value. With `indent` set, nested values go on their
+    /// own lines indented by that many spaces per level.
+    fn to_string(&self, indent: Option<usize>) -> String {
+        let mut out = String::new();
+        self.write(&mut out, indent, 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: Option<usize>, level: usize) {
+        let newline = |out: &mut String, level: usize| {
+            if let Some(width) = indent {
+                out.push('\n');
+                out.push_str(&" ".repeat(width * level));
+            }
+        };
+
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Number(number) => out.push_str(number),
+            Json::String(text) => {
+                out.push('"');
+                for c in text.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    item.write(out, indent, level + 1);
+                }
+                if !items.is_empty() {
+                    newline(out, level);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    Json::String(key.to_string()).write(out, indent, level + 1);
+                    out.push(':');
+                    if indent.is_some() {
+                        out.push(' ');
+                    }
+                    value.write(out, indent, level + 1);
+                }
+                if !fields.is_empty() {
+                    newline(out, level);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// Lists the devices left after filtering, as `node: name` lines, and
+/// tells the sinks about each.
+fn announce_devices(output: &OutputConfig, sinks: &mut Sinks, devices: &[(String, &str)]) {
+    for (index, (_, name)) in devices.iter().enumerate() {
+        sinks.device(output, true, index, name);
+    }
+    if output.verbosity == Verbosity::Verbose {
+        println!("Monitoring {} device(s):", devices.len());
+        for (node, name) in devices {
+            println!("{}: {}", node, name);
+        }
+        println!();
+    }
+}
+
+/// Confirms the measurement loop is about to start.
+fn announce_ready(output: &OutputConfig) {
+    if output.verbosity == Verbosity::Verbose {
+        println!("Ready.");
+    }
+}
+
+/// Timestamped `--checkpoint` lines with each device's current average,
+/// a coarse time series for long captures.
+struct Checkpoint {
+    every: Duration,
+    next: Instant,
+    /// With `--remote`, each device's index and last sent rate.
+    sent: Vec<(String, String)>,
+}
+
+impl Checkpoint {
+    fn new(output: &OutputConfig) -> Option<Self> {
+        output.checkpoint.map(|every| Self {
+            every,
+            next: Instant::now() + every,
+            sent: Vec::new(),
+        })
+    }
+
+    /// `+INDEX NAME` the first time a device reports, then `INDEX RATE`
+    /// whenever its rate changed since the last line.
+    fn send_remote<'a>(&mut self, output: &OutputConfig, stats: impl Iterator<Item = &'a DeviceStats>) {
+        for stats in stats.filter(|stats| stats.avg_hz > 0) {
+            let rate = output.format_hz(stats.avg_hz_exact);
+            let idx = match self.sent.iter().position(|(name, _)| *name == stats.name) {
+                Some(idx) if self.sent[idx].1 == rate => continue,
+                Some(idx) => idx,
+                None => {
+                    println!("+{} {}", self.sent.len(), stats.name);
+                    self.sent.push((stats.name.clone(), String::new()));
+                    self.sent.len() - 1
+                }
+            };
+            println!("{} {}", idx, rate);
+            self.sent[idx].1 = rate;
+        }
+    }
+
+    /// Prints a checkpoint once one is due; called from the backend loops.
+    fn tick<'a>(&mut self, output: &OutputConfig, stats: impl Iterator<Item = &'a DeviceStats>) {
+        if Instant::now() < self.next {
+            return;
+        }
+        // Keep to the original schedule so checkpoints don't drift
+        while self.next <= Instant::now() {
+            self.next += self.every;
+        }
+
+        if output.remote {
+            return self.send_remote(output, stats);
+        }
+        let time = now_iso8601();
+        for stats in stats.filter(|stats| stats.avg_hz > 0) {
+            println!(
+                "{} Checkpoint {} {}{}",
+                time,
+                stats.label(),
+                output.padded_hz(stats.avg_hz_exact),
+                output.unit.suffix()
+            );
+        }
+    }
+}
+
+/// Name of the aggregate entry measured by `--system`.
+const SYSTEM_NAME: &str = "System";
+
+/// Indices of `names` to monitor, in display order. `--system` keeps every
+/// device, since they all feed the aggregate, and the display is checked
+/// against the aggregate instead. Fails if a named device isn't found.
+fn select_devices(output: &OutputConfig, names: &[&str]) -> Result<Vec<usize>, EvhzError> {
+    let pool: Vec<usize> = match &output.device {
+        Some(pattern) => {
+            let idx = find_device(names, pattern, &[]).ok_or_else(|| {
+                EvhzError::NotFound(format!("Could not find a device matching '{}'", pattern))
+            })?;
+            vec![idx]
+        }
+        None => (0..names.len()).collect(),
+    };
+    let pooled: Vec<&str> = pool.iter().map(|&idx| names[idx]).collect();
+    let shown: &[&str] = if output.system { &[SYSTEM_NAME] } else { &pooled };
+    let selected = output.display.select(shown)?;
+    if output.system {
+        Ok(pool)
+    } else {
+        Ok(selected.into_iter().map(|idx| pool[idx]).collect())
+    }
+}
+
+/// Pads every device name to the longest one once enumeration is done.
+fn align_names(stats: &mut [&mut DeviceStats]) {
+    let width = stats.iter().map(|s| s.display_name().chars().count()).max().unwrap_or(0);
+    for s in stats {
+        s.name_width = width;
+    }
+}
+
+/// First device whose name contains `pattern` (case-insensitive), skipping
+/// indices already taken.
+fn find_device(names: &[&str], pattern: &str, taken: &[usize]) -> Option<usize> {
+    let pattern = pattern.to_lowercase();
+    names
+        .iter()
+        .enumerate()
+        .position(|(idx, name)| !taken.contains(&idx) && name.to_lowercase().contains(&pattern))
+}
+
+/// Two devices tracked head to head by `--compare`.
+#[derive(Clone)]
+struct Compare {
+    names: [String; 2],
+}
+
+impl Compare {
+    /// Picks the first device matching each side, never the same device twice.
+    fn resolve(&self, names: &[&str]) -> Option<[usize; 2]> {
+        let a = find_device(names, &self.names[0], &[])?;
+        let b = find_device(names, &self.names[1], &[a])?;
+        Some([a, b])
+    }
+}
+
+impl Display {
+    /// Indices of the devices to keep monitoring, in the order the display
+    /// uses them. `Err(EvhzError::NotFound)` when a named device is missing,
+    /// leaving the caller to report it.
+    fn select(&self, names: &[&str]) -> Result<Vec<usize>, EvhzError> {
+        match self {
+            Display::Compare(compare) => {
+                let pair = compare.resolve(names).ok_or_else(|| {
+                    EvhzError::NotFound(format!(
+                        "Could not find both devices to compare: '{}' and '{}'",
+                        compare.names[0], compare.names[1]
+                    ))
+                })?;
+                Ok(pair.to_vec())
+            }
+            Display::Gauge(pattern) => {
+                let idx = find_device(names, pattern, &[]).ok_or_else(|| {
+                    EvhzError::NotFound(format!("Could not find a device matching '{}'", pattern))
+                })?;
+                Ok(vec![idx])
+            }
+            Display::Scroll | Display::Top(_) => Ok((0..names.len()).collect()),
+        }
+    }
+}
+
+/// Render state for the refreshing displays. Backends hand it the selected
+/// devices, in `Display::select` order, once per loop iteration.
+enum LiveView {
+    Compare(CompareView),
+    Top(TopView),
+    Gauge(GaugeView),
+}
+
+impl LiveView {
+    fn new(display: &Display) -> Option<Self> {
+        match display {
+            Display::Scroll => None,
+            Display::Compare(_) => Some(LiveView::Compare(CompareView::default())),
+            Display::Top(count) => Some(LiveView::Top(TopView::new(*count))),
+            Display::Gauge(_) => Some(LiveView::Gauge(GaugeView::default())),
+        }
+    }
+
+    fn render(&mut self, output: &OutputConfig, stats: &[&DeviceStats]) {
+        match self {
+            LiveView::Compare(view) => view.render(output, stats[0], stats[1]),
+            LiveView::Top(view) => view.render(output, stats.iter().copied()),
+            LiveView::Gauge(view) => view.render(output, stats[0]),
+        }
+    }
+}
+
+/// Notices terminal resizes between redraws of the in-place displays. The
+/// size is polled on each redraw rather than read from crossterm's event
+/// stream, which would compete with the pause reader for stdin.
+#[derive(Default)]
+struct ResizeWatch {
+    size: Option<(u16, u16)>,
+}
+
+impl ResizeWatch {
+    /// Returns the terminal width and whether the size changed since the
+    /// last call. After a change the caller clears the screen and redraws
+    /// from the top, since the old output has been reflowed.
+    fn check(&mut self) -> (usize, bool) {
+        let size = crossterm::terminal::size().unwrap_or((80, 24));
+        let resized = self.size.is_some_and(|old| old != size);
+        self.size = Some(size);
+        (size.0 as usize, resized)
+    }
+}
+
+/// Cuts `line` to `cols` characters so it can't wrap and throw off the
+/// cursor movements that redraw it.
+fn clip(line: &str, cols: usize) -> &str {
+    match line.char_indices().nth(cols) {
+        Some((end, _)) => &line[..end],
+        None => line,
+    }
+}
+
+/// The `--progress` status line on stderr: the fastest device's live rate,
+/// rewritten in place so stdout stays free for data.
+struct ProgressLine {
+    last_draw: Option<Instant>,
+}
+
+impl ProgressLine {
+    const REFRESH: Duration = Duration::from_millis(250);
+
+    /// Only with `--progress` and a terminal on stderr.
+    fn new(output: &OutputConfig) -> Option<Self> {
+        // Built lazily: dropping one clears the line, even unused
+        (output.progress && std::io::stderr().is_terminal()).then(|| ProgressLine { last_draw: None })
+    }
+
+    fn render<'a>(&mut self, output: &OutputConfig, stats: impl Iterator<Item = &'a DeviceStats>) {
+        if self.last_draw.is_some_and(|last| last.elapsed() < Self::REFRESH) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let top = stats.map(|stats| (stats, stats.live_hz(output))).max_by(|a, b| a.1.total_cmp(&b.1));
+        let line = match top {
+            Some((stats, hz)) if hz > 0.0 => format!("{} {}{}", stats.name, output.format_hz(hz), output.unit.suffix()),
+            _ => "waiting for events".to_string(),
+        };
+        let cols = crossterm::terminal::size().map_or(80, |(cols, _)| cols as usize);
+        let mut err = std::io::stderr().lock();
+        let _ = write!(err, "\r{}\x1b[K", clip(&line, cols.saturating_sub(1)));
+        let _ = err.flush();
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// Single refreshing `--compare` line.
+#[derive(Default)]
+struct CompareView {
+    last_line: String,
+    resize: ResizeWatch,
+}
+
+impl CompareView {
+    fn render(&mut self, output: &OutputConfig, a: &DeviceStats, b: &DeviceStats) {
+        let (a_hz, b_hz) = (a.live_hz(output), b.live_hz(output));
+        let delta = output.round_hz(a_hz) - output.round_hz(b_hz);
+        let unit = output.unit.suffix();
+        let compose = |a_name: &str, b_name: &str| {
+            format!(
+                "{} {}{} | {} {}{} | Δ {}{}",
+                a_name,
+                output.format_hz(a_hz),
+                unit,
+                b_name,
+                output.format_hz(b_hz),
+                unit,
+                output.signed_hz(delta),
+                unit
+            )
+        };
+        let line = compose(&a.name, &b.name);
+        if !output.ansi {
+            if line != self.last_line {
+                println!("{}", line);
+            }
+            self.last_line = line;
+            return;
+        }
+
+        let (cols, resized) = self.resize.check();
+        if resized {
+            print!("\x1b[2J\x1b[H");
+        } else if line == self.last_line {
+            return;
+        }
+        // Color only a line that fits, as escapes would throw off the clip
+        let clipped = clip(&line, cols.saturating_sub(1));
+        if clipped.len() == line.len() && output.color {
+            let colored = compose(&output.paint(&a.name, &a.name), &output.paint(&b.name, &b.name));
+            print!("\r{}\x1b[K", colored);
+        } else {
+            print!("\r{}\x1b[K", clipped);
+        }
+        let _ = std::io::stdout().flush();
+        self.last_line = line;
+    }
+}
+
+/// Refreshing `--top` table of the fastest devices.
+struct TopView {
+    count: usize,
+    drawn: usize,
+    last_draw: Option<Instant>,
+    resize: ResizeWatch,
+}
+
+impl TopView {
+    const REFRESH: Duration = Duration::from_millis(250);
+
+    fn new(count: usize) -> Self {
+        Self {
+            count,
+            drawn: 0,
+            last_draw: None,
+            resize: ResizeWatch::default(),
+        }
+    }
+
+    /// Redraws the table in place, at most every `REFRESH`. Without ANSI
+    /// support each refresh is appended as a block followed by a blank line.
+    fn render<'a>(&mut self, output: &OutputConfig, stats: impl Iterator<Item = &'a DeviceStats>) {
+        if self.last_draw.is_some_and(|last| last.elapsed() < Self::REFRESH) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let mut ranked: Vec<(&DeviceStats, f64)> = stats
+            .map(|s| (s, s.live_hz(output)))
+            .filter(|&(_, hz)| hz > 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(self.count);
+
+        let mut out = std::io::stdout().lock();
+        if !output.ansi {
+            for (s, hz) in &ranked {
+                let _ = writeln!(out, "{} {}{}", s.label(), output.padded_hz(*hz), output.unit.suffix());
+            }
+            let _ = writeln!(out);
+            return;
+        }
+
+        let (cols, resized) = self.resize.check();
+        if resized {
+            let _ = write!(out, "\x1b[2J\x1b[H");
+        } else if self.drawn > 0 {
+            let _ = write!(out, "\x1b[{}F", self.drawn);
+        }
+        for (s, hz) in &ranked {
+            let line = format!("{} {}{}", s.label(), output.padded_hz(*hz), output.unit.suffix());
+            let line = output.paint(&s.name, clip(&line, cols.saturating_sub(1)));
+            let _ = writeln!(out, "{}\x1b[K", line);
+        }
+        let _ = write!(out, "\x1b[J");
+        let _ = out.flush();
+        self.drawn = ranked.len();
+    }
+}
+
+/// Polling rates mice are commonly sold at, used to scale the gauge bar and
+/// to bucket samples for `--mode-rate`.
+const STANDARD_RATES: [u32; 7] = [125, 250, 500, 1000, 2000, 4000, 8000];
+
+/// Five-row block glyphs for the gauge's big number.
+const BIG_DIGITS: [[&str; 5]; 10] = [
+    ["███", "█ █", "█ █", "█ █", "███"],
+    ["  █", "  █", "  █", "  █", "  █"],
+    ["███", "  █", "███", "█  ", "███"],
+    ["███", "  █", "███", "  █", "███"],
+    ["█ █", "█ █", "███", "  █", "  █"],
+    ["███", "█  ", "███", "  █", "███"],
+    ["███", "█  ", "███", "█ █", "███"],
+    ["███", "  █", "  █", "  █", "  █"],
+    ["███", "█ █", "███", "█ █", "███"],
+    ["███", "█ █", "███", "  █", "███"],
+];
+
+/// Full-screen `--gauge` showing one device's rate as a big number and bar.
+#[derive(Default)]
+struct GaugeView {
+    peak: u32,
+    last_draw: Option<Instant>,
+    last_hz: Option<u32>,
+    /// Whether we switched to the alternate screen and must restore it.
+    entered: bool,
+}
+
+impl GaugeView {
+    const REFRESH: Duration = Duration::from_millis(50);
+
+    fn render(&mut self, output: &OutputConfig, stats: &DeviceStats) {
+        if self.last_draw.is_some_and(|last| last.elapsed() < Self::REFRESH) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+        let exact = stats.live_hz(output);
+        let hz = output.rounding.apply(exact) as u32;
+        self.peak = self.peak.max(hz);
+
+        if !output.ansi {
+            if self.last_hz != Some(hz) {
+                println!("{} {}{}", stats.label(), output.padded_hz(exact), output.unit.suffix());
+            }
+            self.last_hz = Some(hz);
+            return;
+        }
+
+        let _ = self.draw(stats, hz);
+    }
+
+    fn draw(&mut self, stats: &DeviceStats, hz: u32) -> std::io::Result<()> {
+        use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+        use crossterm::{cursor, queue, terminal};
+
+        let mut out = std::io::stdout().lock();
+        if !self.entered {
+            queue!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+            self.entered = true;
+        }
+
+        let (cols, rows) = terminal::size().unwrap_or((80, 24));
+        let digits: Vec<usize> = hz
+            .to_string()
+            .bytes()
+            .map(|b| (b - b'0') as usize)
+            .collect();
+        let number: Vec<String> = (0..5)
+            .map(|row| {
+                let glyphs: Vec<&str> = digits.iter().map(|&d| BIG_DIGITS[d][row]).collect();
+                glyphs.join(" ")
+            })
+            .collect();
+
+        let scale = STANDARD_RATES
+            .iter()
+            .copied()
+            .find(|&rate| rate >= self.peak)
+            .unwrap_or(self.peak)
+            .max(1);
+        // Layout follows the current size on every frame, so resizes just
+        // take effect on the next redraw; the bar never outgrows the screen
+        let bar_width = (cols as usize * 2 / 3).max(10).min((cols as usize).saturating_sub(2));
+        let filled = (hz as usize * bar_width / scale as usize).min(bar_width);
+
+        // Name, blank, five rows of digits, blank, bar, scale
+        let top = rows.saturating_sub(10) / 2;
+        let center = |width: usize| (cols as usize).saturating_sub(width) as u16 / 2;
+
+        queue!(out, cursor::MoveTo(0, top), terminal::Clear(terminal::ClearType::All))?;
+        queue!(
+            out,
+            cursor::MoveTo(center(stats.name.chars().count()), top),
+            Print(&stats.name)
+        )?;
+
+        queue!(out, SetForegroundColor(Color::Rgb { r: 255, g: 140, b: 0 }))?;
+        let number_width = number[0].chars().count() + 3;
+        for (row, line) in number.iter().enumerate() {
+            queue!(out, cursor::MoveTo(center(number_width), top + 2 + row as u16), Print(line))?;
+        }
+        queue!(out, Print(" Hz"))?;
+
+        // Bar shades from yellow to red as it fills
+        queue!(out, cursor::MoveTo(center(bar_width + 2), top + 8), ResetColor, Print("["))?;
+        for cell in 0..bar_width {
+            if cell < filled {
+                let g = 220 - (160 * cell / bar_width) as u8;
+                queue!(out, SetForegroundColor(Color::Rgb { r: 255, g, b: 0 }), Print("█"))?;
+            } else {
+                queue!(out, ResetColor, Print(" "))?;
+            }
+        }
+        queue!(out, ResetColor, Print("]"))?;
+
+        let footer = format!("peak {}Hz / scale {}Hz", self.peak, scale);
+        queue!(
+            out,
+            cursor::MoveTo(center(footer.chars().count()), top + 9),
+            Print(footer)
+        )?;
+
+        out.flush()
+    }
+}
+
+impl Drop for GaugeView {
+    fn drop(&mut self) {
+        if self.entered {
+            use crossterm::{cursor, execute, terminal};
+            let _ = execute!(std::io::stdout(), terminal::LeaveAlternateScreen, cursor::Show);
+        }
+    }
+}
+
+/// Sensor resolutions for `--dpi`, asked of libratbag through its
+/// `ratbagctl` tool. The kernel has no standard DPI attribute, so mice it
+/// doesn't know, or systems without ratbagd running, simply go without.
+#[cfg(target_os = "linux")]
+mod ratbag {
+    use std::process::{Command, Stdio};
+
+    /// `ratbagctl`'s `id: name` device list.
+    pub struct Ratbag {
+        devices: Vec<(String, String)>,
+    }
+
+    impl Ratbag {
+        pub fn list() -> Self {
+            let devices = ratbagctl(&["list"]).map_or_else(Vec::new, |list| parse_list(&list));
+            Self { devices }
+        }
+
+        /// The active resolution of the device whose name matches `name`
+        /// either way round, ignoring case.
+        pub fn dpi(&self, name: &str) -> Option<u32> {
+            let name = name.to_lowercase();
+            let (id, _) = self.devices.iter().find(|(_, known)| {
+                let known = known.to_lowercase();
+                name.contains(&known) || known.contains(&name)
+            })?;
+            parse_dpi(&ratbagctl(&[id, "dpi", "get"])?)
+        }
+    }
+
+    fn ratbagctl(args: &[&str]) -> Option<String> {
+        let out = Command::new("ratbagctl").args(args).stderr(Stdio::null()).output().ok()?;
+        out.status.success().then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    /// Lines like `warbling-mara:   Logitech G403 HERO Gaming Mouse`.
+    pub fn parse_list(list: &str) -> Vec<(String, String)> {
+        list.lines()
+            .filter_map(|line| {
+                let (id, name) = line.split_once(':')?;
+                let (id, name) = (id.trim(), name.trim());
+                (!id.is_empty() && !id.contains(' ') && !name.is_empty()).then(|| (id.to_string(), name.to_string()))
+            })
+            .collect()
+    }
+
+    /// `1600dpi`, or `800x800dpi` for separate axes, of which x is taken.
+    pub fn parse_dpi(text: &str) -> Option<u32> {
+        let digits: String = text.trim().chars().take_while(char::is_ascii_digit).collect();
+        digits.parse().ok().filter(|&dpi| dpi > 0)
+    }
+}
+
+/// The focused X11 window's class for `--focus-window`. libX11 is loaded
+/// with `dlopen` when asked for, so evhz neither links against it nor needs
+/// it installed otherwise.
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::ffi::{c_char, c_int, c_long, c_uchar, c_ulong, c_void};
+
+    type Window = c_ulong;
+    type Atom = c_ulong;
+    /// `XA_WINDOW`, the type `_NET_ACTIVE_WINDOW` holds.
+    const XA_WINDOW: Atom = 33;
+
+    #[repr(C)]
+    struct XClassHint {
+        res_name: *mut c_char,
+        res_class: *mut c_char,
+    }
+
+    type ErrorHandler = unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_int;
+    type GetWindowProperty = unsafe extern "C" fn(
+        *mut c_void,
+        Window,
+        Atom,
+        c_long,
+        c_long,
+        c_int,
+        Atom,
+        *mut Atom,
+        *mut c_int,
+        *mut c_ulong,
+        *mut c_ulong,
+        *mut *mut c_uchar,
+    ) -> c_int;
+    type GetClassHint = unsafe extern "C" fn(*mut c_void, Window, *mut XClassHint) -> c_int;
+    type Free = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+    /// An open display with the few Xlib functions the query needs.
+    pub struct FocusQuery {
+        display: *mut c_void,
+        root: Window,
+        active_window: Atom,
+        get_window_property: GetWindowProperty,
+        get_class_hint: GetClassHint,
+        free: Free,
+    }
+
+    // Only ever used from the one thread that polls it
+    unsafe impl Send for FocusQuery {}
+
+    /// The focused window can close between two calls, and Xlib's default
+    /// handler would exit the process over the resulting BadWindow.
+    unsafe extern "C" fn ignore_error(_: *mut c_void, _: *mut c_void) -> c_int {
+        0
+    }
+
+    impl FocusQuery {
+        /// Loads libX11 and connects to `$DISPLAY`.
+        pub fn open() -> Result<Self, String> {
+            unsafe {
+                let lib = libc::dlopen(c"libX11.so.6".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+                if lib.is_null() {
+                    return Err("libX11.so.6 is not installed".to_string());
+                }
+                let symbol = |name: &std::ffi::CStr| {
+                    let ptr = libc::dlsym(lib, name.as_ptr());
+                    (!ptr.is_null()).then_some(ptr).ok_or_else(|| format!("libX11 lacks {:?}", name))
+                };
+                let open_display: unsafe extern "C" fn(*const c_char) -> *mut c_void =
+                    std::mem::transmute(symbol(c"XOpenDisplay")?);
+                let default_root: unsafe extern "C" fn(*mut c_void) -> Window =
+                    std::mem::transmute(symbol(c"XDefaultRootWindow")?);
+                let intern_atom: unsafe extern "C" fn(*mut c_void, *const c_char, c_int) -> Atom =
+                    std::mem::transmute(symbol(c"XInternAtom")?);
+                let set_error_handler: unsafe extern "C" fn(Option<ErrorHandler>) -> Option<ErrorHandler> =
+                    std::mem::transmute(symbol(c"XSetErrorHandler")?);
+                let get_window_property: GetWindowProperty = std::mem::transmute(symbol(c"XGetWindowProperty")?);
+                let get_class_hint: GetClassHint = std::mem::transmute(symbol(c"XGetClassHint")?);
+                let free: Free = std::mem::transmute(symbol(c"XFree")?);
+
+                let display = open_display(std::ptr::null());
+                if display.is_null() {
+                    return Err("cannot open the X display; is DISPLAY set?".to_string());
+                }
+                set_error_handler(Some(ignore_error));
+                Ok(Self {
+                    display,
+                    root: default_root(display),
+                    active_window: intern_atom(display, c"_NET_ACTIVE_WINDOW".as_ptr(), 0),
+                    get_window_property,
+                    get_class_hint,
+                    free,
+                })
+            }
+        }
+
+        /// The focused window's `WM_CLASS` instance and class names, or
+        /// `None` when nothing is focused or the window manager doesn't say.
+        pub fn active_class(&self) -> Option<(String, String)> {
+            unsafe {
+                let (mut kind, mut format, mut items, mut after) = (0, 0, 0, 0);
+                let mut data: *mut c_uchar = std::ptr::null_mut();
+                let status = (self.get_window_property)(
+                    self.display,
+                    self.root,
+                    self.active_window,
+                    0,
+                    1,
+                    0,
+                    XA_WINDOW,
+                    &mut kind,
+                    &mut format,
+                    &mut items,
+                    &mut after,
+                    &mut data,
+                );
+                if data.is_null() {
+                    return None;
+                }
+                // Format 32 properties come back as C longs whatever their width
+                let window = (status == 0 && format == 32 && items == 1).then(|| *(data as *const c_ulong));
+                (self.free)(data.cast());
+
+                let mut hint = XClassHint {
+                    res_name: std::ptr::null_mut(),
+                    res_class: std::ptr::null_mut(),
+                };
+                let window = window.filter(|&window| window != 0)?;
+                if (self.get_class_hint)(self.display, window, &mut hint) == 0 {
+                    return None;
+                }
+                let take = |text: *mut c_char| {
+                    if text.is_null() {
+                        return String::new();
+                    }
+                    let owned = std::ffi::CStr::from_ptr(text).to_string_lossy().into_owned();
+                    (self.free)(text.cast());
+                    owned
+                };
+                Some((take(hint.res_name), take(hint.res_class)))
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use evdev::{AbsoluteAxisType, BusType, Device, InputEvent, InputEventKind, RelativeAxisType, Synchronization};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Case-insensitive name fragments of devices created by software rather
+    /// than hardware, skipped by `--no-virtual`.
+    const VIRTUAL_NAME_PATTERNS: &[&str] = &["virtual", "xtest", "uinput", "ydotool", "input-remapper"];
+
+    /// Reads the polling rate from the interrupt IN endpoint of the USB
+    /// interface behind an event node. The kernel exposes the decoded
+    /// `bInterval` as e.g. `1ms` or `125us`, whatever the bus speed.
+    fn declared_hz(node: &str) -> Option<u32> {
+        let device = fs::canonicalize(format!("/sys/class/input/{}/device", node)).ok()?;
+        let interface = device.ancestors().find_map(|dir| {
+            fs::read_dir(dir).ok()?.flatten().map(|entry| entry.path()).find(|endpoint| {
+                endpoint.file_name().is_some_and(|name| name.to_string_lossy().starts_with("ep_"))
+                    && fs::read_to_string(endpoint.join("type")).is_ok_and(|kind| kind.trim() == "Interrupt")
+                    && fs::read_to_string(endpoint.join("direction")).is_ok_and(|dir| dir.trim() == "in")
+            })
+        })?;
+
+        let interval = fs::read_to_string(interface.join("interval")).ok()?;
+        let interval = interval.trim();
+        let micros: u32 = match interval.strip_suffix("ms") {
+            Some(ms) => ms.parse::<u32>().ok()? * 1000,
+            None => interval.strip_suffix("us")?.parse().ok()?,
+        };
+        1_000_000u32.checked_div(micros)
+    }
+
+    /// The `--debug-devices` block: identity and every capability bitmap
+    /// evdev reports for the device.
+    fn describe(path: &Path, device: &Device) -> String {
+        fn names<T: std::fmt::Debug>(codes: impl Iterator<Item = T>) -> String {
+            let names: Vec<String> = codes.map(|code| format!("{:?}", code)).collect();
+            if names.is_empty() { "(none)".to_string() } else { names.join(", ") }
+        }
+
+        let id = device.input_id();
+        let (major, minor, patch) = device.driver_version();
+        let mut lines = vec![
+            format!("Device {}: {}", path.display(), device.name().unwrap_or("Unknown")),
+            format!(
+                "  Input ID: bus {} (0x{:04x}), vendor 0x{:04x}, product 0x{:04x}, version 0x{:04x}",
+                id.bus_type(),
+                id.bus_type().0,
+                id.vendor(),
+                id.product(),
+                id.version()
+            ),
+            format!("  Driver version: {}.{}.{}", major, minor, patch),
+            format!("  Physical path: {}", device.physical_path().unwrap_or("(none)")),
+            format!("  Unique ID: {}", device.unique_name().filter(|id| !id.is_empty()).unwrap_or("(none)")),
+            format!("  Properties: {}", names(device.properties().iter())),
+            format!("  Events: {}", names(device.supported_events().iter())),
+        ];
+        let bitmaps = [
+            ("Keys", device.supported_keys().map(|set| names(set.iter()))),
+            ("Relative axes", device.supported_relative_axes().map(|set| names(set.iter()))),
+            ("Absolute axes", device.supported_absolute_axes().map(|set| names(set.iter()))),
+            ("Switches", device.supported_switches().map(|set| names(set.iter()))),
+            ("LEDs", device.supported_leds().map(|set| names(set.iter()))),
+            ("Misc", device.misc_properties().map(|set| names(set.iter()))),
+            ("Force feedback", device.supported_ff().map(|set| names(set.iter()))),
+            ("Sounds", device.supported_sounds().map(|set| names(set.iter()))),
+        ];
+        for (label, codes) in bitmaps {
+            if let Some(codes) = codes {
+                lines.push(format!("  {}: {}", label, codes));
+            }
+        }
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    fn is_virtual(device: &Device) -> bool {
+        let name = device.name().unwrap_or_default().to_lowercase();
+        VIRTUAL_NAME_PATTERNS.iter().any(|pattern| name.contains(pattern))
+            || device.input_id().bus_type() == BusType::BUS_VIRTUAL
+            || device.physical_path().is_none_or(str::is_empty)
+    }
+
+    /// What changed since a device's last `SYN_REPORT`. A report counts as
+    /// one sample, and only when it carried a meaningful event.
+    #[derive(Default)]
+    struct Frame {
+        /// Counts for the device itself: motion, or the `--key` key.
+        device: bool,
+        scroll: bool,
+        /// Sum of `|REL_X|` and `|REL_Y|`, for `--speed-buckets`.
+        motion: u32,
+        /// Multitouch slots that reported, a bit per slot, for `--per-slot`.
+        slots: u16,
+        /// `REL_X` and `REL_Y` moved, bits 0 and 1, for `--per-axis`.
+        axes: u8,
+    }
+
+    /// The `--per-slot` companions. Touches in higher slots only count
+    /// towards the device itself.
+    const SLOT_NAMES: [&str; 16] = [
+        "Slot 0", "Slot 1", "Slot 2", "Slot 3", "Slot 4", "Slot 5", "Slot 6", "Slot 7",
+        "Slot 8", "Slot 9", "Slot 10", "Slot 11", "Slot 12", "Slot 13", "Slot 14", "Slot 15",
+    ];
+
+    /// The `--per-axis` companions, in `Frame::axes` bit order.
+    const AXIS_NAMES: [&str; 2] = ["X", "Y"];
+
+    /// The `--speed-buckets` companion a report moving `motion` counts falls
+    /// into.
+    fn speed_bucket(motion: u32) -> &'static str {
+        match motion {
+            0..=4 => "Slow",
+            5..=19 => "Medium",
+            _ => "Fast",
+        }
+    }
+
+    type Companions = std::collections::HashMap<(PathBuf, &'static str), DeviceStats>;
+
+    /// Counts an event into the `kind` companion of the device at `path`,
+    /// or the aggregate under `--system`.
+    fn update_companion(
+        companions: &mut Companions,
+        system: &mut Option<DeviceStats>,
+        device: &DeviceStats,
+        (path, kind): (&std::path::Path, &'static str),
+        now: Timestamp,
+        output: &OutputConfig,
+        sinks: &mut Sinks,
+    ) {
+        match system {
+            Some(system) => system.update(now, output, sinks),
+            None => companions
+                .entry((path.to_path_buf(), kind))
+                .or_insert_with(|| device.companion(kind))
+                .update(now, output, sinks),
+        }
+    }
+
+    /// The node whose stats events from `path` count into: itself, or the
+    /// first node of the same name under `--merge-similar`.
+    fn owner<'a>(merged: &'a std::collections::HashMap<PathBuf, PathBuf>, path: &'a PathBuf) -> &'a PathBuf {
+        merged.get(path).unwrap_or(path)
+    }
+
+    /// The aggregate under `--system`, otherwise the device's own stats.
+    fn route<'a>(system: &'a mut Option<DeviceStats>, stats: &'a mut DeviceStats) -> &'a mut DeviceStats {
+        match system {
+            Some(system) => system,
+            None => stats,
+        }
+    }
+
+    /// Pauses between attempts to open devices that were busy or denied.
+    const OPEN_RETRY_DELAYS: [Duration; 3] = [
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+        Duration::from_millis(200),
+    ];
+
+    /// Opens an event node with `O_NONBLOCK` set, so a poll wakeup that finds
+    /// nothing to read returns `WouldBlock` instead of stalling the loop.
+    fn open_nonblocking(path: &std::path::Path) -> std::io::Result<Device> {
+        use std::os::unix::io::AsRawFd;
+
+        let device = Device::open(path)?;
+        let fd = device.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(device)
+    }
+
+    /// Opens the paths one at a time and hands each device to `offer`, which
+    /// keeps it or drops, and so closes, it straight away, and returns
+    /// whether it wants more. Once it doesn't, no further path is opened.
+    /// Paths that fail with EBUSY or EACCES, e.g. while a compositor briefly
+    /// holds them at startup, are retried afterwards; missing nodes aren't.
+    /// Returns how many paths were left unopened.
+    fn open_each(paths: Vec<PathBuf>, mut offer: impl FnMut(PathBuf, Device) -> bool) -> usize {
+        let transient = |err: &std::io::Error| {
+            matches!(err.raw_os_error(), Some(libc::EBUSY | libc::EACCES))
+        };
+
+        let mut pending = Vec::new();
+        let mut paths = paths.into_iter();
+        for path in paths.by_ref() {
+            match open_nonblocking(&path) {
+                Ok(device) => {
+                    if !offer(path, device) {
+                        return paths.len() + pending.len();
+                    }
+                }
+                Err(err) if transient(&err) => pending.push((path, err)),
+                Err(_) => {}
+            }
+        }
+
+        // Retry everything still failing together, so a batch of
+        // inaccessible nodes costs one backoff schedule rather than one each
+        for delay in OPEN_RETRY_DELAYS {
+            if pending.is_empty() {
+                break;
+            }
+            std::thread::sleep(delay);
+            let mut still_failing = Vec::new();
+            let mut retried = pending.into_iter();
+            for (path, _) in retried.by_ref() {
+                match open_nonblocking(&path) {
+                    Ok(device) => {
+                        if !offer(path, device) {
+                            return retried.len() + still_failing.len();
+                        }
+                    }
+                    Err(err) if transient(&err) => still_failing.push((path, err)),
+                    Err(_) => {}
+                }
+            }
+            pending = still_failing;
+        }
+
+        for (path, err) in &pending {
+            log_event(
+                Severity::Error,
+                &format!("{}: cannot open after {} retries: {}", path.display(), OPEN_RETRY_DELAYS.len(), err),
+            );
+        }
+        0
+    }
+
+    /// An event node's device name from sysfs, read without opening it.
+    fn sysfs_name(path: &Path) -> Option<String> {
+        let node = path.file_name()?.to_string_lossy();
+        let name = fs::read_to_string(format!("/sys/class/input/{}/device/name", node)).ok()?;
+        Some(name.trim_end().to_string())
+    }
+
+    /// The error for a scan of `dir` that left nothing to monitor: a
+    /// permission problem if any `prefix*` node couldn't be opened.
+    fn no_devices(dir: &'static str, prefix: &str) -> EvhzError {
+        let denied = fs::read_dir(dir).into_iter().flatten().flatten().any(|entry| {
+            entry.file_name().to_string_lossy().starts_with(prefix)
+                && fs::File::open(entry.path())
+                    .is_err_and(|err| err.kind() == std::io::ErrorKind::PermissionDenied)
+        });
+        if denied {
+            return EvhzError::PermissionDenied(dir);
+        }
+        EvhzError::NoDevices(format!("No matching input devices found in {}", dir))
+    }
+
+    pub fn run(
+        output: &OutputConfig,
+        capture: &CaptureConfig,
+        controls: &Controls,
+        sinks: &mut Sinks,
+    ) -> Result<RunSummary, EvhzError> {
+        let started = Instant::now();
+        let mut devices = Vec::new();
+        let mut stats_map: std::collections::HashMap<PathBuf, DeviceStats> = std::collections::HashMap::new();
+        // Created on first use so wheel-less devices don't list a scroll entry
+        let mut companions = Companions::new();
+
+        // Scan /dev/input/event* devices; a missing directory just means
+        // there are none
+        let entries = match fs::read_dir("/dev/input") {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(EvhzError::NoDevices("No input devices found, /dev/input doesn't exist".to_string()));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(EvhzError::PermissionDenied("/dev/input"));
+            }
+            Err(err) => return Err(EvhzError::Io("Failed to read /dev/input", err)),
+        };
+        let paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+            })
+            .collect();
+
+        // Nodes counted into another node's stats under --merge-similar
+        let mut merged: std::collections::HashMap<PathBuf, PathBuf> = std::collections::HashMap::new();
+
+        // A saved selection falls back to paths only for names that are gone,
+        // so it needs every node's name before any is opened
+        let found: Vec<String> = match &capture.devices_file {
+            Some(_) => paths.iter().filter_map(|path| sysfs_name(path)).collect(),
+            None => Vec::new(),
+        };
+        let found: Vec<&str> = found.iter().map(String::as_str).collect();
+
+        let ratbag = capture.dpi.then(ratbag::Ratbag::list);
+        // Rejected devices are dropped, closing them, as soon as they're seen
+        let unopened = open_each(paths, |path, device| {
+            let dev_name = device.name().unwrap_or("Unknown").to_string();
+            let filtered = match &capture.devices_file {
+                Some(saved) if !saved.contains(&path.to_string_lossy(), &dev_name, &found) => return true,
+                Some(_) => false,
+                None => true,
+            };
+            if capture.key.is_some_and(|key| !device.supported_keys().is_some_and(|keys| key.supported_by(keys)))
+                || (filtered && capture.no_virtual && is_virtual(&device))
+            {
+                return true;
+            }
+            let node = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if filtered && capture.excludes(output, &node, &dev_name) {
+                return true;
+            }
+            if capture.merge_similar
+                && let Some(owner) = stats_map.iter().find(|(_, stats)| stats.name == dev_name)
+            {
+                merged.insert(path.clone(), owner.0.clone());
+            } else {
+                let mut stats = DeviceStats::new(dev_name);
+                if device.input_id().bus_type() == BusType::BUS_USB {
+                    stats.declared_hz = declared_hz(&node);
+                }
+                if let Some(ratbag) = &ratbag {
+                    stats.dpi = ratbag.dpi(&stats.name);
+                }
+                stats_map.insert(path.clone(), stats);
+            }
+            devices.push((path, device, 0));
+            !capture.at_max_devices(devices.len())
+        });
+
+        capture.warn_max_devices(unopened);
+        if devices.is_empty() {
+            return Err(no_devices("/dev/input", "event"));
+        }
+
+        // Displays naming devices keep only those open
+        let names: Vec<&str> =
+            devices.iter().map(|(path, _, _)| stats_map[owner(&merged, path)].name.as_str()).collect();
+        let mut selected: Vec<PathBuf> = select_devices(output, &names)?
+            .into_iter()
+            .map(|idx| owner(&merged, &devices[idx].0).clone())
+            .collect();
+        selected.dedup();
+        devices.retain(|(path, _, _)| selected.contains(owner(&merged, path)));
+        stats_map.retain(|path, _| selected.contains(path));
+        if let Some(file) = &capture.save_devices {
+            let entries = devices
+                .iter()
+                .map(|(path, _, _)| (path.to_string_lossy().into_owned(), stats_map[owner(&merged, path)].name.clone()))
+                .collect();
+            if let Err(err) = fs::write(file, SavedDevices(entries).render()) {
+                eprintln!("Failed to write {}: {}", file.display(), err);
+            }
+        }
+        let listed: Vec<(String, &str)> = devices
+            .iter()
+            .map(|(path, _, _)| {
+                let node = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                (node, stats_map[owner(&merged, path)].name.as_str())
+            })
+            .collect();
+        announce_devices(output, sinks, &listed);
+        // Each node's place in that list, for its `device_removed`
+        let indices: std::collections::HashMap<PathBuf, usize> =
+            devices.iter().enumerate().map(|(idx, (path, _, _))| (path.clone(), idx)).collect();
+        // Taken now, while even devices that later fail are still open
+        let mut device_info: Vec<(PathBuf, String)> = if capture.debug_devices {
+            devices.iter().map(|(path, device, _)| (path.clone(), describe(path, device))).collect()
+        } else {
+            Vec::new()
+        };
+        let mut view = LiveView::new(&output.display);
+        let mut system = output.system.then(|| DeviceStats::new(SYSTEM_NAME.to_string()));
+        align_names(&mut stats_map.values_mut().collect::<Vec<_>>());
+        // Every device may also grow scroll, FF and LED companions
+        let per_device = 2
+            + capture.count_ff as usize
+            + capture.count_led as usize
+            + 3 * capture.speed_buckets as usize
+            + SLOT_NAMES.len() * capture.per_slot as usize
+            + AXIS_NAMES.len() * capture.per_axis as usize;
+        let output = &output.fit_memory(stats_map.len() * per_device);
+
+        // Use select to wait for events with timeout
+        use std::os::unix::io::AsRawFd;
+
+        let mut pause = PauseWatch::default();
+        let mut checkpoint = Checkpoint::new(output);
+        let mut progress = ProgressLine::new(output);
+        announce_ready(output);
+        let mut frames: std::collections::HashMap<PathBuf, Frame> = std::collections::HashMap::new();
+        // State of the --while-held key, tracked across all devices
+        let mut held = false;
+        let mut clicks: std::collections::HashMap<PathBuf, ClickTiming> = std::collections::HashMap::new();
+        // Last counted value of each absolute axis, for --abs-threshold
+        let mut abs_values: std::collections::HashMap<(PathBuf, u16), i32> = std::collections::HashMap::new();
+        let mut auto_select = capture.auto_select.then(|| Instant::now() + AUTO_SELECT_AFTER);
+        // Slot the next ABS_MT_* event of each multitouch node belongs to
+        let mut mt_slots: std::collections::HashMap<PathBuf, usize> = std::collections::HashMap::new();
+        // Devices already warned about with a `BACKLOG_REPORTS` backlog
+        let mut backlogged: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
+
+        while controls.running() {
+            controls.beat();
+            let paused = pause.check(
+                controls,
+                stats_map.values_mut().chain(companions.values_mut()).chain(system.iter_mut()),
+            );
+
+            let mut fds: Vec<libc::pollfd> = devices.iter().map(|(_, device, _)| {
+                libc::pollfd {
+                    fd: device.as_raw_fd(),
+                                                                events: libc::POLLIN,
+                                                                revents: 0,
+                }
+            }).collect();
+
+            // Poll with 100ms timeout so we can check running flag
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 100) };
+
+            if ret > 0 {
+                for (idx, (path, device, errors)) in devices.iter_mut().enumerate() {
+                    let revents = fds[idx].revents;
+                    if revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                        *errors += 1;
+                        continue;
+                    }
+                    if revents & libc::POLLIN == 0 {
+                        continue;
+                    }
+
+                    match device.fetch_events() {
+                        Ok(events) => {
+                            // The batch's clock reading, taken as soon as it was read
+                            let read_at = Timestamp::now();
+                            *errors = 0;
+                            if paused {
+                                frames.remove(path);
+                                continue;
+                            }
+                            let events: Vec<InputEvent> = events.collect();
+                            let is_report = |event: &&InputEvent| {
+                                event.kind() == InputEventKind::Synchronization(Synchronization::SYN_REPORT)
+                            };
+                            let last_report = events.iter().rev().find(is_report).map(InputEvent::timestamp);
+                            let reports = events.iter().filter(is_report).count();
+                            if reports > BACKLOG_REPORTS && backlogged.insert(path.clone()) {
+                                log_event(
+                                    Severity::Warning,
+                                    &format!(
+                                        "{}: {} reports of {} arrived in one read, the poll loop is falling behind; \
+                                         timing backlogged reports by their kernel timestamps",
+                                        path.display(),
+                                        reports,
+                                        stats_map[owner(&merged, path)].name
+                                    ),
+                                );
+                            }
+                            let frame = frames.entry(path.clone()).or_default();
+                            let owner_path = owner(&merged, path);
+                            for event in events {
+                                // Reports that queued up behind the last one keep the
+                                // kernel's spacing instead of all sharing `read_at`
+                                let now = match last_report {
+                                    Some(last) => read_at.minus(last.duration_since(event.timestamp()).unwrap_or_default()),
+                                    None => read_at,
+                                };
+                                // Presses and releases, not autorepeat
+                                if capture.click_timing
+                                    && let InputEventKind::Key(key) = event.kind()
+                                {
+                                    let timing = clicks.entry(owner_path.clone()).or_default();
+                                    match event.value() {
+                                        1 => timing.press(key.code(), now),
+                                        0 => timing.release(key.code(), now),
+                                        _ => {}
+                                    }
+                                }
+                                // Still counted for the device below, as the merged rate
+                                if capture.per_slot
+                                    && let InputEventKind::AbsAxis(axis) = event.kind()
+                                    && axis.0 >= AbsoluteAxisType::ABS_MT_SLOT.0
+                                {
+                                    let slot = mt_slots.entry(path.clone()).or_default();
+                                    let bit = 1u16.checked_shl(*slot as u32).unwrap_or(0);
+                                    match axis {
+                                        AbsoluteAxisType::ABS_MT_SLOT => *slot = event.value().max(0) as usize,
+                                        // A lift ends the touch, so the next one in the slot
+                                        // isn't timed from this one
+                                        AbsoluteAxisType::ABS_MT_TRACKING_ID if event.value() < 0 => {
+                                            frame.slots &= !bit;
+                                            if let Some(name) = SLOT_NAMES.get(*slot)
+                                                && let Some(stats) = companions.get_mut(&(owner_path.clone(), *name))
+                                            {
+                                                stats.prev_time = None;
+                                            }
+                                        }
+                                        _ => frame.slots |= bit,
+                                    }
+                                }
+                                match event.kind() {
+                                    InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                                        if frame.device
+                                            && let Some(stats) = stats_map.get_mut(owner_path)
+                                        {
+                                            let previous = stats.prev_time;
+                                            route(&mut system, stats).update(now, output, sinks);
+                                            // Timed from the device's previous report of any
+                                            // speed, not the bucket's own previous one
+                                            if capture.speed_buckets && frame.motion > 0 {
+                                                let kind = speed_bucket(frame.motion);
+                                                let bucket = companions
+                                                    .entry((owner_path.clone(), kind))
+                                                    .or_insert_with(|| stats.companion(kind));
+                                                bucket.prev_time = previous;
+                                                bucket.update(now, output, sinks);
+                                            }
+                                        }
+                                        if frame.scroll {
+                                            let device = &stats_map[owner_path];
+                                            update_companion(
+                                                &mut companions, &mut system, device, (owner_path, "Scroll"), now, output, sinks,
+                                            );
+                                        }
+                                        for (slot, name) in SLOT_NAMES.iter().enumerate() {
+                                            if frame.slots & (1 << slot) != 0 {
+                                                let device = &stats_map[owner_path];
+                                                update_companion(
+                                                    &mut companions, &mut system, device, (owner_path, name), now, output, sinks,
+                                                );
+                                            }
+                                        }
+                                        for (bit, name) in AXIS_NAMES.iter().enumerate() {
+                                            if frame.axes & (1 << bit) != 0 {
+                                                let device = &stats_map[owner_path];
+                                                update_companion(
+                                                    &mut companions, &mut system, device, (owner_path, name), now, output, sinks,
+                                                );
+                                            }
+                                        }
+                                        *frame = Frame::default();
+                                    }
+                                    // The kernel dropped events, so the partial report is unreliable
+                                    InputEventKind::Synchronization(Synchronization::SYN_DROPPED) => {
+                                        *frame = Frame::default();
+                                    }
+                                    InputEventKind::Key(key) if capture.while_held == Some(key) => {
+                                        let now_held = event.value() != 0;
+                                        if now_held != held {
+                                            // Don't measure across the press or release
+                                            for stats in stats_map.values_mut().chain(system.iter_mut()) {
+                                                stats.prev_time = None;
+                                            }
+                                            frame.device = false;
+                                            held = now_held;
+                                        }
+                                    }
+                                    // Not part of SYN_REPORT frames, so counted one by one
+                                    InputEventKind::ForceFeedback(_) if capture.count_ff => {
+                                        let device = &stats_map[owner_path];
+                                        update_companion(&mut companions, &mut system, device, (owner_path, "FF"), now, output, sinks);
+                                    }
+                                    InputEventKind::Led(_) if capture.count_led => {
+                                        let device = &stats_map[owner_path];
+                                        update_companion(&mut companions, &mut system, device, (owner_path, "LED"), now, output, sinks);
+                                    }
+                                    // Presses and autorepeat, not releases
+                                    kind if let Some(filter) = capture.key => {
+                                        frame.device |= matches!(kind, InputEventKind::Key(key) if filter.matches(key))
+                                            && event.value() != 0;
+                                    }
+                                    // Hi-res wheel events duplicate the notch events
+                                    InputEventKind::RelAxis(
+                                        RelativeAxisType::REL_WHEEL_HI_RES
+                                        | RelativeAxisType::REL_HWHEEL_HI_RES,
+                                    ) => {}
+                                    InputEventKind::RelAxis(
+                                        RelativeAxisType::REL_WHEEL | RelativeAxisType::REL_HWHEEL,
+                                    ) => frame.scroll = true,
+                                    // Compared against the last counted value, not the last
+                                    // seen one, so slow drift still adds up to a change
+                                    InputEventKind::AbsAxis(axis)
+                                        if let Some(threshold) = capture.abs_threshold =>
+                                    {
+                                        let (key, value) = ((path.clone(), axis.0), event.value());
+                                        let changed = abs_values
+                                            .get(&key)
+                                            .is_none_or(|&last| value.abs_diff(last) > threshold as u32);
+                                        if changed {
+                                            abs_values.insert(key, value);
+                                        }
+                                        frame.device |= changed && (held || capture.while_held.is_none());
+                                    }
+                                    InputEventKind::RelAxis(axis @ (RelativeAxisType::REL_X | RelativeAxisType::REL_Y)) => {
+                                        let counted = held || capture.while_held.is_none();
+                                        frame.motion += event.value().unsigned_abs();
+                                        frame.device |= counted;
+                                        if capture.per_axis && counted {
+                                            frame.axes |= if axis == RelativeAxisType::REL_X { 1 } else { 2 };
+                                        }
+                                    }
+                                    InputEventKind::RelAxis(_) | InputEventKind::AbsAxis(_) => {
+                                        frame.device |= held || capture.while_held.is_none();
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                        // Readable per poll but drained already; not a failure
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(_) => *errors += 1,
+                    }
+                }
+
+                // Stop polling devices that keep failing, e.g. after an unplug
+                devices.retain(|(path, _, errors)| {
+                    if *errors < MAX_READ_ERRORS {
+                        return true;
+                    }
+                    let name = &stats_map[owner(&merged, path)].name;
+                    log_event(
+                        Severity::Warning,
+                        &format!("{}: {} stopped responding, no longer monitoring it", path.display(), name),
+                    );
+                    sinks.device(output, false, indices[path], name);
+                    false
+                });
+            }
+
+            // Waits past the deadline until some device has sent anything
+            if let Some(deadline) = auto_select
+                && Instant::now() >= deadline
+                && let Some(busiest) = stats_map
+                    .iter()
+                    .filter(|(_, stats)| stats.count > 0)
+                    .max_by_key(|(_, stats)| stats.count)
+                    .map(|(path, _)| path.clone())
+            {
+                log_event(
+                    Severity::Info,
+                    &format!("Auto-selected {}: {}", busiest.display(), stats_map[&busiest].name),
+                );
+                devices.retain(|(path, _, _)| {
+                    let kept = *owner(&merged, path) == busiest;
+                    if !kept {
+                        sinks.device(output, false, indices[path], &stats_map[owner(&merged, path)].name);
+                    }
+                    kept
+                });
+                stats_map.retain(|path, _| *path == busiest);
+                companions.retain(|(path, _), _| *path == busiest);
+                device_info.retain(|(path, _)| *owner(&merged, path) == busiest);
+                selected.retain(|path| *path == busiest);
+                auto_select = None;
+            }
+
+            if let Some(view) = &mut view {
+                let shown: Vec<&DeviceStats> = match &system {
+                    Some(system) => vec![system],
+                    None => selected.iter().map(|path| &stats_map[path]).collect(),
+                };
+                view.render(output, &shown);
+            }
+            if let Some(checkpoint) = &mut checkpoint {
+                match &system {
+                    Some(system) => checkpoint.tick(output, std::iter::once(system)),
+                    None => checkpoint.tick(output, stats_map.values().chain(companions.values())),
+                }
+            }
+            match &system {
+                Some(system) => snapshot_if_requested(output, std::iter::once(system)),
+                None => snapshot_if_requested(output, stats_map.values().chain(companions.values())),
+            }
+            if let Some(progress) = &mut progress {
+                match &system {
+                    Some(system) => progress.render(output, std::iter::once(system)),
+                    None => progress.render(output, stats_map.values()),
+                }
+            }
+        }
+        drop(view);
+
+        let clicks = stats_map
+            .iter()
+            .filter_map(|(path, stats)| clicks.remove(path).map(|timing| (stats.label(), timing)))
+            .collect();
+        let devices = match system {
+            Some(system) => vec![system],
+            None => stats_map.into_values().chain(companions.into_values()).collect(),
+        };
+        Ok(RunSummary {
+            clicks,
+            device_info: device_info.into_iter().map(|(_, info)| info).collect(),
+            ..RunSummary::new(devices, started, output)
+        })
+    }
+
+    /// Measures raw HID report cadence by timestamping every read from
+    /// `/dev/hidraw*`, bypassing evdev's processing entirely.
+    pub fn run_hidraw(
+        output: &OutputConfig,
+        capture: &CaptureConfig,
+        controls: &Controls,
+        sinks: &mut Sinks,
+    ) -> Result<RunSummary, EvhzError> {
+        let started = Instant::now();
+        let mut devices = Vec::new();
+        let mut nodes = Vec::new();
+        let mut unopened = 0;
+
+        use std::os::unix::fs::OpenOptionsExt;
+        let entries = fs::read_dir("/dev").map_err(|err| EvhzError::Io("Failed to read /dev", err))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let node = entry.file_name().to_string_lossy().into_owned();
+            if !node.starts_with("hidraw") {
+                continue;
+            }
+            // Named from sysfs, so filtered and capped nodes are never opened
+            let dev_name = hidraw_name(&node);
+            if capture.excludes(output, &node, &dev_name) {
+                continue;
+            }
+            if capture.at_max_devices(devices.len()) {
+                unopened += 1;
+                continue;
+            }
+            // Nonblocking like the event nodes, so a spurious wakeup can't stall
+            if let Ok(file) = fs::OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path) {
+                nodes.push(node);
+                devices.push((Some(file), DeviceStats::new(dev_name), 0));
+            }
+        }
+
+        capture.warn_max_devices(unopened);
+        if devices.is_empty() {
+            return Err(no_devices("/dev", "hidraw"));
+        }
+
+        // Reorder into display order, dropping devices the display doesn't show
+        let names: Vec<&str> = devices.iter().map(|(_, stats, _)| stats.name.as_str()).collect();
+        let selected = select_devices(output, &names)?;
+        let listed: Vec<(String, &str)> =
+            selected.iter().map(|&idx| (nodes[idx].clone(), names[idx])).collect();
+        announce_devices(output, sinks, &listed);
+
+        let mut found: Vec<_> = devices.into_iter().map(Some).collect();
+        let mut devices: Vec<_> = selected.into_iter().filter_map(|idx| found[idx].take()).collect();
+        let mut view = LiveView::new(&output.display);
+        let mut system = output.system.then(|| DeviceStats::new(SYSTEM_NAME.to_string()));
+        align_names(&mut devices.iter_mut().map(|(_, stats, _)| stats).collect::<Vec<_>>());
+        let output = &output.fit_memory(devices.len());
+
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let mut report = [0u8; 4096];
+        let mut pause = PauseWatch::default();
+        let mut checkpoint = Checkpoint::new(output);
+        let mut progress = ProgressLine::new(output);
+        announce_ready(output);
+
+        while controls.running() {
+            controls.beat();
+            let paused = pause.check(
+                controls,
+                devices.iter_mut().map(|(_, stats, _)| stats).chain(system.iter_mut()),
+            );
+
+            // Closed devices get a negative fd, which poll ignores
+            let mut fds: Vec<libc::pollfd> = devices.iter().map(|(file, _, _)| {
+                libc::pollfd {
+                    fd: file.as_ref().map_or(-1, |file| file.as_raw_fd()),
+                    events: libc::POLLIN,
+                    revents: 0,
+                }
+            }).collect();
+
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 100) };
+
+            if ret > 0 {
+                for (idx, (file, stats, errors)) in devices.iter_mut().enumerate() {
+                    let Some(open) = file else { continue };
+                    let revents = fds[idx].revents;
+                    if revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                        *errors += 1;
+                    } else if revents & libc::POLLIN != 0 {
+                        // Each read returns exactly one report
+                        match open.read(&mut report) {
+                            Ok(n) if n > 0 => {
+                                *errors = 0;
+                                if !paused {
+                                    route(&mut system, stats).update_now(output, sinks);
+                                }
+                            }
+                            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                            _ => *errors += 1,
+                        }
+                    }
+
+                    // Failing devices stay listed for the summary but are no longer polled
+                    if *errors >= MAX_READ_ERRORS {
+                        log_event(
+                            Severity::Warning,
+                            &format!("{} stopped responding, no longer monitoring it", stats.name),
+                        );
+                        sinks.device(output, false, idx, &stats.name);
+                        *file = None;
+                    }
+                }
+
+            }
+
+            if let Some(view) = &mut view {
+                let shown: Vec<&DeviceStats> = match &system {
+                    Some(system) => vec![system],
+                    None => devices.iter().map(|(_, stats, _)| stats).collect(),
+                };
+                view.render(output, &shown);
+            }
+            if let Some(checkpoint) = &mut checkpoint {
+                match &system {
+                    Some(system) => checkpoint.tick(output, std::iter::once(system)),
+                    None => checkpoint.tick(output, devices.iter().map(|(_, stats, _)| stats)),
+                }
+            }
+            match &system {
+                Some(system) => snapshot_if_requested(output, std::iter::once(system)),
+                None => snapshot_if_requested(output, devices.iter().map(|(_, stats, _)| stats)),
+            }
+            if let Some(progress) = &mut progress {
+                match &system {
+                    Some(system) => progress.render(output, std::iter::once(system)),
+                    None => progress.render(output, devices.iter().map(|(_, stats, _)| stats)),
+                }
+            }
+        }
+        drop(view);
+
+        let devices = match system {
+            Some(system) => vec![system],
+            None => devices.into_iter().map(|(_, stats, _)| stats).collect(),
+        };
+        Ok(RunSummary::new(devices, started, output))
+    }
+
+    /// Looks up the HID device name for a hidraw node via sysfs.
+    fn hidraw_name(node: &str) -> String {
+        fs::read_to_string(format!("/sys/class/hidraw/{}/device/uevent", node))
+            .ok()
+            .and_then(|uevent| {
+                uevent
+                    .lines()
+                    .find_map(|line| line.strip_prefix("HID_NAME="))
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| "Unknown".to_string())
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use windows::core::w;
+    use windows::Win32::Foundation::{HWND, LPARAM};
+    use windows::Win32::UI::Input::{
+        GetRawInputData, RegisterRawInputDevices, HRAWINPUT, RAWINPUT, RAWINPUTDEVICE,
+        RAWINPUTHEADER, RIDEV_INPUTSINK, RID_INPUT, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DispatchMessageW, MsgWaitForMultipleObjects, PeekMessageW, HWND_MESSAGE,
+        MSG, PM_REMOVE, QS_RAWINPUT, RI_KEY_BREAK, RI_MOUSE_HWHEEL, RI_MOUSE_WHEEL,
+        WINDOW_EX_STYLE, WINDOW_STYLE, WM_INPUT,
+    };
+
+    const MOUSE: usize = 0;
+    const KEYBOARD: usize = 1;
+    const SCROLL: usize = 2;
+
+    pub fn run(
+        output: &OutputConfig,
+        _capture: &CaptureConfig,
+        controls: &Controls,
+        sinks: &mut Sinks,
+    ) -> Result<RunSummary, EvhzError> {
+        let started = Instant::now();
+        // Under --system one entry takes every event
+        let names: &[&str] = if output.system { &[SYSTEM_NAME] } else { &["Mouse", "Keyboard", "Scroll"] };
+        let slot = |idx: usize| if output.system { 0 } else { idx };
+        let mut stats: Vec<DeviceStats> = names.iter().map(|name| DeviceStats::new(name.to_string())).collect();
+
+        let selected = select_devices(output, names)?;
+        let listed: Vec<(String, &str)> =
+            selected.iter().map(|&idx| (format!("device{}", idx), names[idx])).collect();
+        announce_devices(output, sinks, &listed);
+        let mut view = LiveView::new(&output.display);
+        align_names(&mut stats.iter_mut().collect::<Vec<_>>());
+        let output = &output.fit_memory(stats.len());
+
+        let hwnd = register_raw_input().map_err(EvhzError::RawInput)?;
+        let mut held_keys = [false; 256];
+        let mut pause = PauseWatch::default();
+        let mut checkpoint = Checkpoint::new(output);
+        let mut progress = ProgressLine::new(output);
+        announce_ready(output);
+
+        while controls.running() {
+            controls.beat();
+            // Wait up to 100ms for input so we can check running flag
+            unsafe {
+                MsgWaitForMultipleObjects(None, false, 100, QS_RAWINPUT);
+            }
+            let paused = pause.check(controls, stats.iter_mut());
+
+            let mut msg = MSG::default();
+            while unsafe { PeekMessageW(&mut msg, hwnd, 0, 0, PM_REMOVE) }.as_bool() {
+                // Stamped as soon as the message is dequeued, before decoding
+                let time = Timestamp::now();
+                if msg.message == WM_INPUT
+                    && !paused
+                    && let Some(input) = read_raw_input(msg.lParam)
+                {
+                    if input.header.dwType == RIM_TYPEMOUSE.0 {
+                        let mouse = unsafe { input.data.mouse };
+                        let buttons = unsafe { mouse.Anonymous.Anonymous.usButtonFlags } as u32;
+                        if mouse.lLastX != 0 || mouse.lLastY != 0 {
+                            stats[slot(MOUSE)].update(time, output, sinks);
+                        }
+                        if buttons & (RI_MOUSE_WHEEL | RI_MOUSE_HWHEEL) != 0 {
+                            stats[slot(SCROLL)].update(time, output, sinks);
+                        }
+                    } else if input.header.dwType == RIM_TYPEKEYBOARD.0 {
+                        // Count presses only, not autorepeat or releases
+                        let keyboard = unsafe { input.data.keyboard };
+                        let down = keyboard.Flags as u32 & RI_KEY_BREAK == 0;
+                        let held = &mut held_keys[keyboard.VKey as usize & 0xff];
+                        if down && !*held {
+                            stats[slot(KEYBOARD)].update(time, output, sinks);
+                        }
+                        *held = down;
+                    }
+                }
+
+                unsafe {
+                    DispatchMessageW(&msg);
+                }
+            }
+
+            if let Some(view) = &mut view {
+                let shown: Vec<&DeviceStats> = selected.iter().map(|&idx| &stats[idx]).collect();
+                view.render(output, &shown);
+            }
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.tick(output, stats.iter());
+            }
+            if let Some(progress) = &mut progress {
+                progress.render(output, stats.iter());
+            }
+        }
+        drop(view);
+
+        Ok(RunSummary::new(stats, started, output))
+    }
+
+    /// Creates a message-only window and registers it to receive mouse and
+    /// keyboard raw input even while it isn't focused.
+    fn register_raw_input() -> windows::core::Result<HWND> {
+        unsafe {
+            let hwnd = CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                w!("STATIC"),
+                w!("evhz"),
+                WINDOW_STYLE::default(),
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                None,
+                None,
+                None,
+            );
+
+            // Generic desktop page: mouse (2) and keyboard (6)
+            let devices = [2, 6].map(|usage| RAWINPUTDEVICE {
+                usUsagePage: 0x01,
+                usUsage: usage,
+                dwFlags: RIDEV_INPUTSINK,
+                hwndTarget: hwnd,
+            });
+            RegisterRawInputDevices(&devices, std::mem::size_of::<RAWINPUTDEVICE>() as u32)?;
+
+            Ok(hwnd)
+        }
+    }
+
+    fn read_raw_input(lparam: LPARAM) -> Option<RAWINPUT> {
+        let mut input = RAWINPUT::default();
+        let mut size = std::mem::size_of::<RAWINPUT>() as u32;
+
+        let read = unsafe {
+            GetRawInputData(
+                HRAWINPUT(lparam.0),
+                RID_INPUT,
+                Some(&mut input as *mut RAWINPUT as *mut std::ffi::c_void),
+                &mut size,
+                std::mem::size_of::<RAWINPUTHEADER>() as u32,
+            )
+        };
+
+        (read > 0 && read != u32::MAX).then_some(input)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use core_graphics::event::{CGEventField, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement, CGEventTapProxy, CGEventType, EventField};
+    use core_foundation::base::TCFType;
+    use core_foundation::mach_port::{CFMachPort, CFMachPortRef};
+    use core_foundation::runloop::{kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoop};
+    use std::ffi::c_void;
+    use std::sync::Mutex;
+
+    type CGEventRef = *mut c_void;
+    type TapCallback = unsafe extern "C" fn(CGEventTapProxy, u32, CGEventRef, *mut c_void) -> CGEventRef;
+
+    // Declared here rather than through `CGEventTap`, whose `CGEventType`
+    // has no variants for the gesture types below and so can neither
+    // register them nor safely receive them
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGEventTapCreate(
+            tap: CGEventTapLocation,
+            place: CGEventTapPlacement,
+            options: CGEventTapOptions,
+            events_of_interest: u64,
+            callback: TapCallback,
+            user_info: *mut c_void,
+        ) -> CFMachPortRef;
+        fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+        fn CGEventGetIntegerValueField(event: CGEventRef, field: CGEventField) -> i64;
+    }
+
+    // The tap doesn't say which device sent an event, so events are split
+    // into the sources their fields can tell apart
+    const MOUSE: usize = 0;
+    const TABLET: usize = 1;
+    const KEYBOARD: usize = 2;
+    const SCROLL_WHEEL: usize = 3;
+    const TRACKPAD_SCROLL: usize = 4;
+    const SYNTHETIC: usize = 5;
+    const BUTTONS: usize = 6;
+    const GESTURE: usize = 7;
+    const MAGNIFY: usize = 8;
+    const ROTATE: usize = 9;
+    const SWIPE: usize = 10;
+    const PRESSURE: usize = 11;
+    const NAMES: [&str; 12] = [
+        "Mouse",
+        "Tablet",
+        "Keyboard",
+        "Scroll Wheel",
+        "Trackpad Scroll",
+        "Synthetic",
+        "Buttons",
+        "Gesture",
+        "Magnify",
+        "Rotate",
+        "Swipe",
+        "Pressure",
+    ];
+
+    /// `NSEventType` values of trackpad gestures, delivered to taps like
+    /// any other type. `GESTURE` is the stream of touch updates under every
+    /// gesture; the others are the recognized ones, Force Touch included.
+    const TYPE_ROTATE: u32 = 18;
+    const TYPE_GESTURE: u32 = 29;
+    const TYPE_MAGNIFY: u32 = 30;
+    const TYPE_SWIPE: u32 = 31;
+    const TYPE_SMART_MAGNIFY: u32 = 32;
+    const TYPE_PRESSURE: u32 = 34;
+
+    /// Every type the tap listens for.
+    const TAPPED_TYPES: [u32; 20] = [
+        CGEventType::MouseMoved as u32,
+        CGEventType::LeftMouseDragged as u32,
+        CGEventType::RightMouseDragged as u32,
+        CGEventType::OtherMouseDragged as u32,
+        CGEventType::LeftMouseDown as u32,
+        CGEventType::LeftMouseUp as u32,
+        CGEventType::RightMouseDown as u32,
+        CGEventType::RightMouseUp as u32,
+        CGEventType::OtherMouseDown as u32,
+        CGEventType::OtherMouseUp as u32,
+        CGEventType::TabletPointer as u32,
+        CGEventType::KeyDown as u32,
+        CGEventType::KeyUp as u32,
+        CGEventType::ScrollWheel as u32,
+        TYPE_ROTATE,
+        TYPE_GESTURE,
+        TYPE_MAGNIFY,
+        TYPE_SWIPE,
+        TYPE_SMART_MAGNIFY,
+        TYPE_PRESSURE,
+    ];
+
+    /// `kCGMouseEventSubtype` values for tablet pens.
+    const SUBTYPE_TABLET_POINT: i64 = 1;
+    const SUBTYPE_TABLET_PROXIMITY: i64 = 2;
+
+    /// Picks the stats an event belongs to from its type and fields.
+    fn classify(event_type: u32, event: CGEventRef) -> Option<usize> {
+        let field = |field| unsafe { CGEventGetIntegerValueField(event, field) };
+        // Events posted by a process (automation, remote desktop) carry its
+        // pid; hardware events have 0
+        if field(EventField::EVENT_SOURCE_UNIX_PROCESS_ID) != 0 {
+            return Some(SYNTHETIC);
+        }
+
+        const MOVED: u32 = CGEventType::MouseMoved as u32;
+        const LEFT_DRAGGED: u32 = CGEventType::LeftMouseDragged as u32;
+        const RIGHT_DRAGGED: u32 = CGEventType::RightMouseDragged as u32;
+        const OTHER_DRAGGED: u32 = CGEventType::OtherMouseDragged as u32;
+        const LEFT_DOWN: u32 = CGEventType::LeftMouseDown as u32;
+        const LEFT_UP: u32 = CGEventType::LeftMouseUp as u32;
+        const RIGHT_DOWN: u32 = CGEventType::RightMouseDown as u32;
+        const RIGHT_UP: u32 = CGEventType::RightMouseUp as u32;
+        const OTHER_DOWN: u32 = CGEventType::OtherMouseDown as u32;
+        const OTHER_UP: u32 = CGEventType::OtherMouseUp as u32;
+        const TABLET_POINTER: u32 = CGEventType::TabletPointer as u32;
+        const KEY_DOWN: u32 = CGEventType::KeyDown as u32;
+        const KEY_UP: u32 = CGEventType::KeyUp as u32;
+        const SCROLL: u32 = CGEventType::ScrollWheel as u32;
+        match event_type {
+            MOVED | LEFT_DRAGGED | RIGHT_DRAGGED | OTHER_DRAGGED => {
+                match field(EventField::MOUSE_EVENT_SUB_TYPE) {
+                    SUBTYPE_TABLET_POINT | SUBTYPE_TABLET_PROXIMITY => Some(TABLET),
+                    _ => Some(MOUSE),
+                }
+            }
+            // Primary, secondary (right or two-finger) and other clicks
+            LEFT_DOWN | LEFT_UP | RIGHT_DOWN | RIGHT_UP | OTHER_DOWN | OTHER_UP => Some(BUTTONS),
+            TABLET_POINTER => Some(TABLET),
+            KEY_DOWN | KEY_UP => Some(KEYBOARD),
+            // Trackpads and Magic Mice scroll continuously, wheels in lines
+            SCROLL => {
+                if field(EventField::SCROLL_WHEEL_EVENT_IS_CONTINUOUS) != 0 {
+                    Some(TRACKPAD_SCROLL)
+                } else {
+                    Some(SCROLL_WHEEL)
+                }
+            }
+            TYPE_GESTURE => Some(GESTURE),
+            TYPE_MAGNIFY | TYPE_SMART_MAGNIFY => Some(MAGNIFY),
+            TYPE_ROTATE => Some(ROTATE),
+            TYPE_SWIPE => Some(SWIPE),
+            TYPE_PRESSURE => Some(PRESSURE),
+            _ => None,
+        }
+    }
+
+    /// Hands each tapped event to the `Box<dyn Fn>` behind `user_info`,
+    /// passing it on unchanged.
+    unsafe extern "C" fn tap_callback(
+        _proxy: CGEventTapProxy,
+        event_type: u32,
+        event: CGEventRef,
+        user_info: *mut c_void,
+    ) -> CGEventRef {
+        let callback = unsafe { &*(user_info as *const Box<dyn Fn(u32, CGEventRef)>) };
+        callback(event_type, event);
+        event
+    }
+
+    pub fn run(
+        output: &OutputConfig,
+        _capture: &CaptureConfig,
+        controls: &Controls,
+        sinks: &mut Sinks,
+    ) -> Result<RunSummary, EvhzError> {
+        let started = Instant::now();
+        // Under --system one entry takes every event
+        let names: &[&str] = if output.system { &[SYSTEM_NAME] } else { &NAMES };
+        let selected = select_devices(output, names)?;
+        let listed: Vec<(String, &str)> =
+            selected.iter().map(|&idx| (format!("device{}", idx), names[idx])).collect();
+        announce_devices(output, sinks, &listed);
+        let mut view = LiveView::new(&output.display);
+
+        let stats: Vec<DeviceStats> = names.iter().map(|name| DeviceStats::new(name.to_string())).collect();
+        let stats = Arc::new(Mutex::new(stats));
+        align_names(&mut stats.lock().unwrap().iter_mut().collect::<Vec<_>>());
+        let output = &output.fit_memory(names.len());
+
+        // The tap calls back on this thread, from within `run_in_mode`
+        let sinks = Mutex::new(sinks);
+        let callback: Box<dyn Fn(u32, CGEventRef) + '_> = {
+            let stats = stats.clone();
+            let paused = controls.paused.clone();
+            let sinks = &sinks;
+            Box::new(move |event_type, event| {
+                if paused.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                if let Some(idx) = classify(event_type, event) {
+                    let idx = if output.system { 0 } else { idx };
+                    stats.lock().unwrap()[idx].update_now(output, &mut sinks.lock().unwrap());
+                }
+            })
+        };
+        // Outlives the tap: boxed twice so the thin pointer survives the FFI
+        let callback = Box::new(callback);
+
+        let mask = TAPPED_TYPES.iter().fold(0u64, |mask, &event_type| mask | 1 << event_type);
+        let tap = unsafe {
+            CGEventTapCreate(
+                CGEventTapLocation::HID,
+                CGEventTapPlacement::HeadInsertEventTap,
+                CGEventTapOptions::ListenOnly,
+                mask,
+                tap_callback,
+                &*callback as *const Box<dyn Fn(u32, CGEventRef) + '_> as *mut c_void,
+            )
+        };
+        if tap.is_null() {
+            return Err(EvhzError::TapCreationFailed("event tap"));
+        }
+        let event_tap = unsafe { CFMachPort::wrap_under_create_rule(tap) };
+
+        let loop_source = event_tap
+            .create_runloop_source(0)
+            .map_err(|_| EvhzError::TapCreationFailed("run loop source for the event tap"))?;
+
+        let run_loop = CFRunLoop::get_current();
+        run_loop.add_source(&loop_source, unsafe { kCFRunLoopCommonModes });
+        unsafe { CGEventTapEnable(event_tap.as_concrete_TypeRef(), true) };
+
+        let mut pause = PauseWatch::default();
+        let mut checkpoint = Checkpoint::new(output);
+        let mut progress = ProgressLine::new(output);
+        announce_ready(output);
+
+        // Drive the run loop until an event arrives or 100ms pass, so the
+        // running flag and the live display are serviced regularly
+        while controls.running() {
+            controls.beat();
+            CFRunLoop::run_in_mode(
+                unsafe { kCFRunLoopDefaultMode },
+                Duration::from_millis(100),
+                true,
+            );
+
+            let mut stats = stats.lock().unwrap();
+            pause.check(controls, stats.iter_mut());
+
+            if let Some(view) = &mut view {
+                let shown: Vec<&DeviceStats> = selected.iter().map(|&idx| &stats[idx]).collect();
+                view.render(output, &shown);
+            }
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.tick(output, stats.iter());
+            }
+            snapshot_if_requested(output, stats.iter());
+            if let Some(progress) = &mut progress {
+                progress.render(output, stats.iter());
+            }
+        }
+        drop(view);
+        // No more callbacks once the tap is gone
+        run_loop.remove_source(&loop_source, unsafe { kCFRunLoopCommonModes });
+        unsafe { CGEventTapEnable(event_tap.as_concrete_TypeRef(), false) };
+        drop(event_tap);
+        drop(callback);
+
+        let devices = std::mem::take(&mut *stats.lock().unwrap());
+        Ok(RunSummary::new(devices, started, output))
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+mod platform {
+    use super::*;
+    // FreeBSD uses same evdev approach as Linux
+    pub use super::platform::run;
+}
+
+/// Synthetic event intervals cycling between 8000Hz and 500Hz with a little
+/// deterministic jitter, so every branch of the stats path gets exercised.
+fn synthetic_intervals() -> impl Iterator<Item = Duration> {
+    const BASE_MICROS: [u64; 5] = [125, 250, 500, 1000, 2000];
+    (0u64..).map(|i| {
+        let jitter = (i * 7919) % 11;
+        Duration::from_micros(BASE_MICROS[(i / 1000) as usize % BASE_MICROS.len()] + jitter)
+    })
+}
+
+/// One `--stdin` line, `timestamp_us device_name`, as its time and device.
+/// The name runs to the end of the line and may contain spaces.
+fn parse_stdin_event(line: &str) -> Option<(Timestamp, &str)> {
+    let (micros, name) = line.trim().split_once(char::is_whitespace)?;
+    let micros: u64 = micros.parse().ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then_some((Timestamp(micros.checked_mul(1000)?), name))
+}
+
+/// The stats side of `--stdin` and `--replay`, which hand it one event at
+/// a time. Devices are added as their names first appear.
+struct EventFeed {
+    devices: Vec<DeviceStats>,
+    system: Option<DeviceStats>,
+    /// Earliest and latest event time read, the span the input covers.
+    span: Option<(Timestamp, Timestamp)>,
+    checkpoint: Option<Checkpoint>,
+    progress: Option<ProgressLine>,
+}
+
+impl EventFeed {
+    fn new(output: &OutputConfig) -> Self {
+        let feed = Self {
+            devices: Vec::new(),
+            system: output.system.then(|| DeviceStats::new(SYSTEM_NAME.to_string())),
+            span: None,
+            checkpoint: Checkpoint::new(output),
+            progress: ProgressLine::new(output),
+        };
+        announce_ready(output);
+        feed
+    }
+
+    fn push(&mut self, output: &OutputConfig, sinks: &mut Sinks, time: Timestamp, name: &str) {
+        self.span = Some(match self.span {
+            Some((first, last)) => (first.min(time), last.max(time)),
+            None => (time, time),
+        });
+        // `--device` keeps the first matching name, as for real devices
+        if let Some(pattern) = &output.device {
+            let wanted = match self.devices.first() {
+                Some(first) => first.name == name,
+                None => name.to_lowercase().contains(&pattern.to_lowercase()),
+            };
+            if !wanted {
+                return;
+            }
+        }
+
+        let stats = match &mut self.system {
+            Some(system) => system,
+            None => match self.devices.iter().position(|stats| stats.name == name) {
+                Some(idx) => &mut self.devices[idx],
+                None => {
+                    sinks.device(output, true, self.devices.len(), name);
+                    self.devices.push(DeviceStats::new(name.to_string()));
+                    self.devices.last_mut().unwrap()
+                }
+            },
+        };
+        stats.update(time, output, sinks);
+
+        if let Some(checkpoint) = &mut self.checkpoint {
+            match &self.system {
+                Some(system) => checkpoint.tick(output, std::iter::once(system)),
+                None => checkpoint.tick(output, self.devices.iter()),
+            }
+        }
+        #[cfg(unix)]
+        match &self.system {
+            Some(system) => snapshot_if_requested(output, std::iter::once(system)),
+            None => snapshot_if_requested(output, self.devices.iter()),
+        }
+        if let Some(progress) = &mut self.progress {
+            match &self.system {
+                Some(system) => progress.render(output, std::iter::once(system)),
+                None => progress.render(output, self.devices.iter()),
+            }
+        }
+    }
+
+    /// The results, measured over the span the input's timestamps cover
+    /// rather than how long it took to read.
+    fn finish(self, output: &OutputConfig, started: Instant) -> RunSummary {
+        let mut devices = match self.system {
+            Some(system) => vec![system],
+            None => self.devices,
+        };
+        align_names(&mut devices.iter_mut().collect::<Vec<_>>());
+        let span = self.span.map_or(0, |(first, last)| last.nanos_since(first));
+        RunSummary {
+            duration: Duration::from_nanos(span),
+            ..RunSummary::new(devices, started, output)
+        }
+    }
+}
+
+/// Feeds `--stdin` events through the stats pipeline until end of input.
+/// Blank lines and `#` comments are skipped.
+fn run_stdin(output: &OutputConfig, controls: &Controls, sinks: &mut Sinks) -> Result<RunSummary, EvhzError> {
+    use std::io::BufRead;
+
+    let started = Instant::now();
+    let mut feed = EventFeed::new(output);
+    for (number, line) in std::io::stdin().lock().lines().enumerate() {
+        // Ctrl-C can only take effect between lines, as reads block
+        if !controls.running() {
+            break;
+        }
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((time, name)) = parse_stdin_event(&line) else {
+            eprintln!("Skipping stdin line {}: expected `timestamp_us device_name`", number + 1);
+            continue;
+        };
+        feed.push(output, sinks, time, name);
+    }
+    Ok(feed.finish(output, started))
+}
+
+/// Feeds a `--capture` file's events through the stats pipeline, as fast as
+/// they can be read. A truncated or corrupt tail ends the replay early with
+/// a warning, keeping what was read.
+fn run_replay(
+    output: &OutputConfig,
+    path: &std::path::Path,
+    controls: &Controls,
+    sinks: &mut Sinks,
+) -> Result<RunSummary, EvhzError> {
+    let started = Instant::now();
+    let file = std::fs::File::open(path).map_err(|err| EvhzError::Io("Failed to open the --replay file", err))?;
+    // Short reads only mean the file was cut off, which says more than EOF does
+    let truncated = |err: std::io::Error| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => std::io::Error::new(err.kind(), "the file is truncated"),
+        _ => err,
+    };
+    let mut reader = CaptureReader::new(std::io::BufReader::new(file))
+        .map_err(|err| EvhzError::Io("Failed to read the --replay file", truncated(err)))?;
+    let mut feed = EventFeed::new(output);
+    while controls.running() {
+        match reader.next_event() {
+            Ok(Some((time, idx))) => feed.push(output, sinks, time, &reader.names[idx]),
+            Ok(None) => break,
+            Err(err) => {
+                log_event(Severity::Warning, &format!("--replay: stopped early: {}", truncated(err)));
+                break;
+            }
+        }
+    }
+    Ok(feed.finish(output, started))
+}
+
+/// How `--simulate` intervals stray from their period, as a fraction of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SimulatedJitter {
+    /// Evenly anywhere within this far either side.
+    Uniform(f64),
+    /// Normally distributed with this standard deviation.
+    Gaussian(f64),
+}
+
+impl Default for SimulatedJitter {
+    fn default() -> Self {
+        SimulatedJitter::Uniform(0.02)
+    }
+}
+
+impl SimulatedJitter {
+    /// Parses `--simulate-jitter [uniform:|gaussian:]PCT[%]`. Uniform jitter
+    /// stops at 50% so every interval stays well above zero.
+    fn parse(value: &str) -> Option<Self> {
+        let (kind, pct) = value.split_once(':').unwrap_or(("uniform", value));
+        let pct: f64 = pct.trim().trim_end_matches('%').parse().ok()?;
+        if !(0.0..=50.0).contains(&pct) {
+            return None;
+        }
+        match kind.trim() {
+            "uniform" => Some(SimulatedJitter::Uniform(pct / 100.0)),
+            "gaussian" => Some(SimulatedJitter::Gaussian(pct / 100.0)),
+            _ => None,
+        }
+    }
+}
+
+/// Spreads one seed into unrelated ones, so devices seeded `seed + idx`
+/// don't jitter in step.
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// The rates of `--simulate HZ[,HZ...]`, each fast enough to average and
+/// under the `MAX_HZ` cap.
+fn parse_simulated_rates(value: &str) -> Option<Vec<u32>> {
+    value
+        .split(',')
+        .map(|rate| rate.trim().parse().ok().filter(|&hz| (1..MAX_HZ as u32).contains(&hz)))
+        .collect()
+}
+
+/// A `--simulate` device: a fixed period with seeded random jitter.
+struct SimulatedDevice {
+    stats: DeviceStats,
+    period_ns: u64,
+    jitter: SimulatedJitter,
+    next: Timestamp,
+    /// xorshift state for the jitter.
+    seed: u64,
+}
+
+impl SimulatedDevice {
+    fn new(name: String, hz: u32, jitter: SimulatedJitter, start: Timestamp, seed: u64) -> Self {
+        let mut device = Self {
+            stats: DeviceStats::new(name),
+            period_ns: 1_000_000_000 / hz as u64,
+            jitter,
+            next: start,
+            seed: splitmix64(seed) | 1,
+        };
+        device.next = Timestamp(start.0 + device.interval_ns());
+        device
+    }
+
+    /// Uniform in [0, 1).
+    fn random(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        (self.seed >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The gap to the next event, jittered around the period.
+    fn interval_ns(&mut self) -> u64 {
+        let offset = match self.jitter {
+            SimulatedJitter::Uniform(spread) => (self.random() * 2.0 - 1.0) * spread,
+            // Box–Muller; 1 - random() is never 0, so the log is finite
+            SimulatedJitter::Gaussian(stddev) => {
+                let radius = (-2.0 * (1.0 - self.random()).ln()).sqrt();
+                radius * (std::f64::consts::TAU * self.random()).cos() * stddev
+            }
+        };
+        // A wide gaussian can reach below zero; keep events moving forward
+        (self.period_ns as f64 * (1.0 + offset).max(0.01)) as u64
+    }
+}
+
+/// Runs `--simulate` devices through the stats and display pipeline in real
+/// time. Each event is stamped with its own scheduled time, so the rates
+/// hold however coarsely the loop wakes up.
+fn run_simulate(
+    output: &OutputConfig,
+    capture: &CaptureConfig,
+    controls: &Controls,
+    sinks: &mut Sinks,
+) -> Result<RunSummary, EvhzError> {
+    let started = Instant::now();
+    let now = Timestamp::now();
+    let seed = capture.seed.unwrap_or_else(|| {
+        let wall = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        now.0 ^ wall
+    });
+    let mut devices: Vec<SimulatedDevice> = Vec::new();
+    for (idx, &hz) in capture.simulate.iter().enumerate() {
+        let mut name = format!("Simulated {}Hz", hz);
+        let twins = capture.simulate[..idx].iter().filter(|&&other| other == hz).count();
+        if twins > 0 {
+            name = format!("{} #{}", name, twins + 1);
+        }
+        let jitter = capture.simulate_jitter;
+        devices.push(SimulatedDevice::new(name, hz, jitter, now, seed.wrapping_add(idx as u64)));
+    }
+
+    let names: Vec<&str> = devices.iter().map(|device| device.stats.name.as_str()).collect();
+    let selected = select_devices(output, &names)?;
+    let listed: Vec<(String, &str)> =
+        selected.iter().map(|&idx| (format!("simulated{}", idx), names[idx])).collect();
+    announce_devices(output, sinks, &listed);
+
+    let mut found: Vec<_> = devices.into_iter().map(Some).collect();
+    let mut devices: Vec<_> = selected.into_iter().filter_map(|idx| found[idx].take()).collect();
+    let mut view = LiveView::new(&output.display);
+    let mut system = output.system.then(|| DeviceStats::new(SYSTEM_NAME.to_string()));
+    align_names(&mut devices.iter_mut().map(|device| &mut device.stats).collect::<Vec<_>>());
+    let output = &output.fit_memory(devices.len());
+
+    let mut pause = PauseWatch::default();
+    let mut checkpoint = Checkpoint::new(output);
+    let mut progress = ProgressLine::new(output);
+    announce_ready(output);
+
+    while controls.running() {
+        controls.beat();
+        let paused = pause.check(
+            controls,
+            devices.iter_mut().map(|device| &mut device.stats).chain(system.iter_mut()),
+        );
+
+        // Events fall due in time order per device; interleaving across
+        // devices doesn't matter, as each keeps its own stats
+        let now = Timestamp::now();
+        for device in &mut devices {
+            while device.next <= now {
+                if !paused {
+                    let stats = match &mut system {
+                        Some(system) => system,
+                        None => &mut device.stats,
+                    };
+                    stats.update(device.next, output, sinks);
+                }
+                device.next = Timestamp(device.next.0 + device.interval_ns());
+            }
+        }
+
+        let shown: Vec<&DeviceStats> = match &system {
+            Some(system) => vec![system],
+            None => devices.iter().map(|device| &device.stats).collect(),
+        };
+        if let Some(view) = &mut view {
+            view.render(output, &shown);
+        }
+        if let Some(checkpoint) = &mut checkpoint {
+            checkpoint.tick(output, shown.iter().copied());
+        }
+        #[cfg(unix)]
+        snapshot_if_requested(output, shown.iter().copied());
+        if let Some(progress) = &mut progress {
+            progress.render(output, shown.iter().copied());
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    drop(view);
+
+    let devices = match system {
+        Some(system) => vec![system],
+        None => devices.into_iter().map(|device| device.stats).collect(),
+    };
+    Ok(RunSummary::new(devices, started, output))
+}
+
+/// One measurement run with whichever backend the options pick, feeding
+/// `sinks` as it goes.
+fn measure(
+    output: &OutputConfig,
+    capture: &CaptureConfig,
+    controls: &Controls,
+    sinks: &mut Sinks,
+) -> Result<RunSummary, EvhzError> {
+    if capture.stdin {
+        return run_stdin(output, controls, sinks);
+    }
+    if let Some(path) = &capture.replay {
+        return run_replay(output, path, controls, sinks);
+    }
+    if !capture.simulate.is_empty() {
+        return run_simulate(output, capture, controls, sinks);
+    }
+    #[cfg(target_os = "linux")]
+    if capture.hidraw {
+        return platform::run_hidraw(output, capture, controls, sinks);
+    }
+    platform::run(output, capture, controls, sinks)
+}
+
+/// Every device's average in each of several `--runs`, in the order the
+/// devices first measured anything.
+fn run_averages<'a>(runs: &[&'a RunSummary]) -> Vec<(&'a str, Vec<f64>)> {
+    let mut names: Vec<&str> = Vec::new();
+    for device in runs.iter().flat_map(|run| &run.devices).filter(|device| device.avg_hz > 0.0) {
+        if !names.contains(&device.name.as_str()) {
+            names.push(&device.name);
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            let averages = runs
+                .iter()
+                .filter_map(|run| run.devices.iter().find(|device| device.name == name && device.avg_hz > 0.0))
+                .map(|device| device.avg_hz)
+                .collect();
+            (name, averages)
+        })
+        .collect()
+}
+
+/// The mean of `averages` and their sample standard deviation, as the runs
+/// sample the device's behaviour.
+fn spread(averages: &[f64]) -> (f64, f64) {
+    let mean = averages.iter().sum::<f64>() / averages.len() as f64;
+    let squares: f64 = averages.iter().map(|hz| (hz - mean).powi(2)).sum();
+    let stddev = if averages.len() > 1 { (squares / (averages.len() - 1) as f64).sqrt() } else { 0.0 };
+    (mean, stddev)
+}
+
+/// The text lines after several `--runs` on how every device's average
+/// spread across them.
+fn print_spreads(output: &OutputConfig, runs: &[&RunSummary]) {
+    let averages = run_averages(runs);
+    println!();
+    let unit = output.unit.suffix();
+    let width = averages.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0) + 1;
+    for (name, averages) in &averages {
+        let (mean, stddev) = spread(averages);
+        println!(
+            "Runs for {:width$} mean {}{}, stddev {}{} over {} runs",
+            format!("{}:", name),
+            output.padded_hz(mean),
+            unit,
+            output.format_hz(stddev),
+            unit,
+            averages.len()
+        );
+    }
+}
+
+/// The same spread as one JSON document, standing in for the results.
+fn spreads_json(output: &OutputConfig, runs: &[&RunSummary]) -> Json {
+    let devices = run_averages(runs)
+        .iter()
+        .map(|(name, averages)| {
+            let (mean, stddev) = spread(averages);
+            Json::Object(vec![
+                ("name", Json::String(name.to_string())),
+                ("mean_hz", Json::hz(output, mean)),
+                ("stddev_hz", Json::hz(output, stddev)),
+                ("run_avg_hz", Json::Array(averages.iter().map(|&hz| Json::hz(output, hz)).collect())),
+            ])
+        })
+        .collect();
+    let mut fields = output.label_field();
+    fields.extend([
+        ("unit", Json::String(output.unit.suffix().to_string())),
+        ("runs", Json::Number(runs.len().to_string())),
+        ("devices", Json::Array(devices)),
+    ]);
+    Json::Object(fields)
+}
+
+/// Measures how many updates per second the stats layer sustains.
+fn bench() {
+    const SAMPLES: usize = 10_000_000;
+
+    let output = OutputConfig {
+        verbosity: Verbosity::Quiet,
+        ..OutputConfig::default()
+    };
+    let mut stats = DeviceStats::new("Bench".to_string());
+    let mut sinks = Sinks::default();
+    let mut time = Timestamp::now();
+
+    let started = Instant::now();
+    for interval in synthetic_intervals().take(SAMPLES) {
+        time += interval;
+        stats.update(time, &output, &mut sinks);
+    }
+    let elapsed = started.elapsed();
+    std::hint::black_box(stats.avg_hz);
+
+    println!(
+        "{} updates in {:.3}s ({:.1}M updates/s)",
+        SAMPLES,
+        elapsed.as_secs_f64(),
+        SAMPLES as f64 / elapsed.as_secs_f64() / 1_000_000.0
+    );
+}
+
+/// Rounds of `CALIBRATION_UPDATES` synthetic events `--calibrate` times,
+/// keeping the median so a preempted round doesn't skew it.
+const CALIBRATION_ROUNDS: usize = 21;
+const CALIBRATION_UPDATES: usize = 10_000;
+
+/// The typical time `DeviceStats::update` takes per event with this run's
+/// stats options, measured on synthetic events with output switched off.
+fn calibrate(output: &OutputConfig) -> Duration {
+    let output = OutputConfig {
+        verbosity: Verbosity::Quiet,
+        overhead_ns: 0,
+        last_event: None,
+        ..output.clone()
+    };
+    let mut stats = DeviceStats::new("Calibration".to_string());
+    let mut sinks = Sinks::default();
+    let mut time = Timestamp::now();
+    let mut intervals = synthetic_intervals();
+    let mut rounds: Vec<Duration> = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            for interval in intervals.by_ref().take(CALIBRATION_UPDATES) {
+                time += interval;
+                stats.update(time, &output, &mut sinks);
+            }
+            started.elapsed() / CALIBRATION_UPDATES as u32
+        })
+        .collect();
+    std::hint::black_box(stats.avg_hz);
+    rounds.sort();
+    rounds[rounds.len() / 2]
+}
+
+/// A command-line option. The parser and `--help` both read `OPTIONS`, so
+/// every flag is documented as soon as it exists.
+struct OptSpec {
+    name: &'static str,
+    alias: Option<&'static str>,
+    /// Placeholders for the values that follow the option, in order.
+    values: &'static [&'static str],
+    description: &'static str,
+    linux_only: bool,
+}
+
+impl OptSpec {
+    const fn flag(name: &'static str, alias: Option<&'static str>, description: &'static str) -> Self {
+        Self {
+            name,
+            alias,
+            values: &[],
+            description,
+            linux_only: false,
+        }
+    }
+
+    const fn with_values(mut self, values: &'static [&'static str]) -> Self {
+        self.values = values;
+        self
+    }
+
+    const fn linux_only(mut self) -> Self {
+        self.linux_only = true;
+        self
+    }
+}
+
+const OPTIONS: &[OptSpec] = &[
+    OptSpec::flag("--nonverbose", Some("-n"), "nonverbose mode"),
+    OptSpec::flag("--quiet", Some("-q"), "only print the final averages"),
+    OptSpec::flag("--compare", None, "show two devices side by side").with_values(&["A", "B"]),
+    OptSpec::flag("--top", None, "show only the N fastest devices").with_values(&["N"]),
+    OptSpec::flag("--gauge", None, "full-screen gauge for one device").with_values(&["NAME"]),
+    OptSpec::flag("--device", None, "only monitor the first device matching NAME").with_values(&["NAME"]),
+    OptSpec::flag("--probe", None, "sample briefly and print just the average"),
+    OptSpec::flag("--duration", None, "stop measuring after SEC seconds").with_values(&["SEC"]),
+    OptSpec::flag("--timeout-idle", None, "stop once no device has sent an event for SEC seconds")
+        .with_values(&["SEC"]),
+    OptSpec::flag("--runs", None, "measure N runs of --duration and report their spread").with_values(&["N"]),
+    OptSpec::flag("--progress", None, "keep the fastest device's rate on one stderr line"),
+    OptSpec::flag("--windowed", None, "live displays show events in the last second"),
+    OptSpec::flag("--banner", None, "on or off: the startup lines").with_values(&["WHEN"]),
+    OptSpec::flag("--color", None, "auto, always or never redraw in place").with_values(&["WHEN"]),
+    OptSpec::flag("--format-hz", None, "print Hz as int (default) or float").with_values(&["FMT"]),
+    OptSpec::flag("--unit", None, "show rates in hz (default) or khz").with_values(&["UNIT"]),
+    OptSpec::flag("--round", None, "round whole Hz: floor, nearest (default) or ceil").with_values(&["MODE"]),
+    OptSpec::flag("--trim", None, "average without outliers: none (default) or iqr").with_values(&["MODE"]),
+    OptSpec::flag("--columns", None, "summary table of e.g. avg,max,p99 instead of the average lines")
+        .with_values(&["LIST"]),
+    OptSpec::flag("--analyze", None, "check for double-reporting at exit"),
+    OptSpec::flag("--smoothing", None, "mean, median or ewma, e.g. median:32 or ewma:0.05")
+        .with_values(&["KIND[:N]"]),
+    OptSpec::flag("--window-ms", None, "average over the last N milliseconds instead of a sample count")
+        .with_values(&["N"]),
+    OptSpec::flag("--max-memory-mb", None, "cap sample buffers, shrinking large --smoothing windows")
+        .with_values(&["MB"]),
+    OptSpec::flag("--checkpoint", None, "print every device's average every SEC seconds").with_values(&["SEC"]),
+    OptSpec::flag("--count-rate", None, "also count events per second, next to the interval Hz"),
+    OptSpec::flag("--relative", None, "show each live average as a percentage of the session peak"),
+    OptSpec::flag("--label", None, "name this session in the banner, summary and structured output")
+        .with_values(&["TEXT"]),
+    OptSpec::flag("--remote", None, "terse per-second index and rate lines for slow links"),
+    OptSpec::flag("--min-samples", None, "report no average before a device has N samples").with_values(&["N"]),
+    OptSpec::flag("--tail", None, "print each device's last N raw intervals at exit").with_values(&["N"]),
+    OptSpec::flag("--gaps", None, "print how event intervals split across gap ranges at exit"),
+    OptSpec::flag("--mode-rate", None, "print each device's most common rate at exit"),
+    OptSpec::flag("--assert", None, "fail unless a device averages HZ within PCT%, repeatable")
+        .with_values(&["NAME=HZ±PCT%"]),
+    OptSpec::flag("--min-hz", None, "warn at exit about devices averaging below HZ").with_values(&["HZ"]),
+    OptSpec::flag("--compare-previous", None, "show each average's change since the last run"),
+    OptSpec::flag("--gnuplot", None, "write PREFIX.dat and PREFIX.plt plotting the averages over time")
+        .with_values(&["PREFIX"]),
+    OptSpec::flag("--csv", None, "write every sample as a CSV row to PATH").with_values(&["PATH"]),
+    OptSpec::flag("--split-output", None, "write each device's samples to a file of its own in DIR")
+        .with_values(&["DIR"]),
+    OptSpec::flag("--split-format", None, "file format for --split-output: csv or ndjson")
+        .with_values(&["FORMAT"]),
+    OptSpec::flag("--prometheus", None, "write the exit averages as Prometheus metrics to PATH")
+        .with_values(&["PATH"]),
+    OptSpec::flag("--fifo", None, "stream NDJSON samples into a named pipe").with_values(&["PATH"]),
+    OptSpec::flag("--flush-interval", None, "buffer per-event lines, writing them every MS").with_values(&["MS"]),
+    OptSpec::flag("--syslog", None, "send warnings to syslog and only print the averages"),
+    OptSpec::flag("--system", None, "measure all devices together as one System rate"),
+    OptSpec::flag("--no-summary", None, "don't print the final results"),
+    OptSpec::flag("--json", None, "print the final results as JSON"),
+    OptSpec::flag("--json-pretty", None, "print the final results as indented JSON"),
+    OptSpec::flag("--capture", None, "record every event losslessly to a compact binary FILE")
+        .with_values(&["FILE"]),
+    OptSpec::flag("--stdin", None, "read `timestamp_us device_name` lines from stdin"),
+    OptSpec::flag("--replay", None, "analyze events recorded with --capture instead of devices")
+        .with_values(&["FILE"]),
+    OptSpec::flag("--calibrate", None, "time the per-event stats work at startup and report it"),
+    OptSpec::flag("--subtract-overhead", None, "calibrate, then take that overhead off every interval"),
+    OptSpec::flag("--simulate", None, "fabricate devices reporting at these rates, e.g. 1000,500")
+        .with_values(&["HZ,..."]),
+    OptSpec::flag("--simulate-jitter", None, "jitter simulated intervals by PCT%, uniform or gaussian")
+        .with_values(&["[gaussian:]PCT"]),
+    OptSpec::flag("--seed", None, "seed the simulated jitter for reproducible runs").with_values(&["N"]),
+    OptSpec::flag("--bench", Some("bench"), "benchmark the stats pipeline and exit"),
+    OptSpec::flag("--hidraw", None, "time raw HID reports").linux_only(),
+    OptSpec::flag("--key", None, "only count one key, e.g. KEY_A or 30, or any keyboard key")
+        .with_values(&["CODE"])
+        .linux_only(),
+    OptSpec::flag("--no-virtual", None, "skip virtual devices").linux_only(),
+    OptSpec::flag("--exclude", None, "skip devices whose name contains TEXT, repeatable")
+        .with_values(&["TEXT"])
+        .linux_only(),
+    OptSpec::flag("--speed-buckets", None, "also measure slow, medium and fast movement apart").linux_only(),
+    OptSpec::flag("--per-slot", None, "also measure each multitouch slot apart").linux_only(),
+    OptSpec::flag("--per-axis", None, "also measure each mouse's X and Y motion apart").linux_only(),
+    OptSpec::flag("--debug-devices", None, "dump each device's evdev properties after the summary").linux_only(),
+    OptSpec::flag("--dpi", None, "show each mouse's DPI where libratbag can read it").linux_only(),
+    OptSpec::flag("--auto-select", None, "after 2s keep only the device sending the most events").linux_only(),
+    OptSpec::flag("--max-devices", None, "open at most N devices after filtering")
+        .with_values(&["N"])
+        .linux_only(),
+    OptSpec::flag("--merge-similar", None, "count event nodes with the same name as one device").linux_only(),
+    OptSpec::flag("--abs-threshold", None, "count absolute axis events only when the value moves more than N")
+        .with_values(&["N"])
+        .linux_only(),
+    OptSpec::flag("--events", None, "also count ff and/or led events, e.g. ff,led")
+        .with_values(&["KINDS"])
+        .linux_only(),
+    OptSpec::flag("--click-timing", None, "report min/avg/max button hold times at exit").linux_only(),
+    OptSpec::flag("--save-devices", None, "write the monitored devices to FILE")
+        .with_values(&["FILE"])
+        .linux_only(),
+    OptSpec::flag("--devices-file", None, "monitor the devices saved in FILE, skipping filters")
+        .with_values(&["FILE"])
+        .linux_only(),
+    OptSpec::flag("--while-held", None, "only count motion while a button is down, e.g. BTN_SIDE")
+        .with_values(&["CODE"])
+        .linux_only(),
+    OptSpec::flag("--focus-window", None, "only count while an X11 window of this WM_CLASS is focused")
+        .with_values(&["CLASS"])
+        .linux_only(),
+    OptSpec::flag("--allow-no-devices", None, "exit successfully when there is no input to measure"),
+    OptSpec::flag("--profile", None, "start from a preset, see Profiles below").with_values(&["NAME"]),
+    OptSpec::flag("--help", Some("-h"), "show this help"),
+];
+
+/// A named set of flags for a common scenario. Its flags are applied before
+/// the command line's, so anything given explicitly wins.
+struct Profile {
+    name: &'static str,
+    description: &'static str,
+    args: &'static [&'static str],
+}
+
+const PROFILES: &[Profile] = &[
+    Profile {
+        name: "gaming-mouse",
+        description: "long 256-sample window, X and Y rates apart, and a warning under 900Hz",
+        args: &["--smoothing", "mean:256", "--per-axis", "--min-hz", "900"],
+    },
+    Profile {
+        name: "keyboard",
+        description: "only key presses and repeats, one sample per report",
+        args: &["--key", "any"],
+    },
+    Profile {
+        name: "review",
+        description: "summary table with p99, and the gap-range histogram",
+        args: &["--columns", "avg,min,max,p99", "--gaps"],
+    },
+];
+
+/// Replaces each `--profile` with its preset's options, moved ahead of the
+/// explicit ones so those override it. Off Linux a preset's Linux-only
+/// options are left out.
+fn expand_profiles(
+    parsed: Vec<(&'static OptSpec, Vec<String>)>,
+) -> Result<Vec<(&'static OptSpec, Vec<String>)>, String> {
+    let mut presets = Vec::new();
+    let mut explicit = Vec::new();
+
+    for (spec, values) in parsed {
+        if spec.name != "--profile" {
+            explicit.push((spec, values));
+            continue;
+        }
+        let Some(profile) = PROFILES.iter().find(|profile| profile.name == values[0]) else {
+            let names: Vec<&str> = PROFILES.iter().map(|profile| profile.name).collect();
+            return Err(format!("Unknown profile {}, expected one of: {}", values[0], names.join(", ")));
+        };
+        let args: Vec<String> = profile.args.iter().map(|arg| arg.to_string()).collect();
+        presets.extend(
+            parse_args(&args)?
+                .into_iter()
+                .filter(|(spec, _)| !spec.linux_only || cfg!(target_os = "linux")),
+        );
+    }
+
+    presets.extend(explicit);
+    Ok(presets)
+}
+
+fn find_option(arg: &str) -> Option<&'static OptSpec> {
+    OPTIONS
+        .iter()
+        .find(|spec| spec.name == arg || spec.alias == Some(arg))
+}
+
+/// Splits the command line into options and their values. Accepts
+/// `--flag value`, `--flag=value` and clustered short flags like `-nq`.
+fn parse_args(args: &[String]) -> Result<Vec<(&'static OptSpec, Vec<String>)>, String> {
+    let mut parsed = Vec::new();
+    let mut rest = args.iter();
+
+    while let Some(arg) = rest.next() {
+        if let Some(cluster) = arg.strip_prefix('-')
+            && !cluster.starts_with('-')
+            && cluster.chars().count() > 1
+        {
+            for c in cluster.chars() {
+                let short = format!("-{}", c);
+                let spec = find_option(&short).ok_or_else(|| unknown_option(&short))?;
+                if !spec.values.is_empty() {
+                    return Err(format!("{} takes a value and can't be combined with other flags", short));
+                }
+                parsed.push((spec, Vec::new()));
+            }
+            continue;
+        }
+
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let spec = find_option(flag).ok_or_else(|| unknown_option(flag))?;
+        if spec.values.is_empty() && inline.is_some() {
+            return Err(format!("{} doesn't take a value", spec.name));
+        }
+
+        let mut values: Vec<String> = inline.into_iter().collect();
+        while values.len() < spec.values.len() {
+            let Some(value) = rest.next() else {
+                return Err(format!("{} requires {}", spec.name, spec.values.join(" ")));
+            };
+            values.push(value.clone());
+        }
+        parsed.push((spec, values));
+    }
+
+    Ok(parsed)
+}
+
+/// Error for an unrecognized flag, suggesting the closest known one.
+fn unknown_option(arg: &str) -> String {
+    let closest = OPTIONS
+        .iter()
+        .map(|spec| (edit_distance(arg, spec.name), spec.name))
+        .min()
+        .filter(|&(distance, _)| distance <= 2);
+
+    match closest {
+        Some((_, name)) => format!("Unknown option: {} (did you mean {}?) See --help.", arg, name),
+        None => format!("Unknown option: {}. See --help.", arg),
+    }
+}
+
+/// Levenshtein distance, used only for flag suggestions.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn print_help(program: &str) {
+    let columns: Vec<String> = OPTIONS
+        .iter()
+        .map(|spec| {
+            let mut column = match spec.alias {
+                Some(alias) => format!("{}, {}", alias, spec.name),
+                None => spec.name.to_string(),
+            };
+            for value in spec.values {
+                column.push(' ');
+                column.push_str(value);
+            }
+            column
+        })
+        .collect();
+    let width = columns.iter().map(String::len).max().unwrap_or(0);
+
+    println!("Usage: {} [options]", program);
+    for (spec, column) in OPTIONS.iter().zip(&columns) {
+        let platform = if spec.linux_only { " (Linux only)" } else { "" };
+        println!("{:<width$}  {}{}", column, spec.description, platform, width = width);
+    }
+
+    println!();
+    println!("Profiles:");
+    let width = PROFILES.iter().map(|profile| profile.name.len()).max().unwrap_or(0);
+    for profile in PROFILES {
+        println!("{:<width$}  {} ({})", profile.name, profile.description, profile.args.join(" "), width = width);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut output = OutputConfig::default();
+    let mut sinks = Sinks::default();
+    let mut color = ColorChoice::default();
+    // Unset means on, except for runs that end by themselves
+    let mut banner = None;
+    let (mut calibration, mut subtract_overhead) = (false, false);
+    let (mut split_dir, mut split_format) = (None, SplitFormat::default());
+    let mut simulate_options = false;
+    let mut capture = CaptureConfig::default();
+
+    let parsed = match parse_args(&args[1..]).and_then(expand_profiles) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    for (spec, values) in parsed {
+        if spec.linux_only && !cfg!(target_os = "linux") {
+            eprintln!("{} is only supported on Linux", spec.name);
+            std::process::exit(EXIT_USAGE);
+        }
+
+        match spec.name {
+            "--help" => {
+                print_help(&args[0]);
+                return;
+            }
+            "--nonverbose" => {
+                output.verbosity = Verbosity::Normal;
+            }
+            "--quiet" => {
+                output.verbosity = Verbosity::Quiet;
+            }
+            #[cfg(target_os = "linux")]
+            "--hidraw" => {
+                capture.hidraw = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--key" => {
+                let Some(key) = KeyFilter::parse(&values[0]) else {
+                    eprintln!("--key requires a key name like KEY_SPACE, a numeric code or any");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.key = Some(key);
+            }
+            #[cfg(target_os = "linux")]
+            "--focus-window" => {
+                capture.focus_window = Some(values[0].clone());
+            }
+            #[cfg(target_os = "linux")]
+            "--no-virtual" => {
+                capture.no_virtual = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--exclude" => {
+                capture.exclude.push(values[0].clone());
+            }
+            #[cfg(target_os = "linux")]
+            "--devices-file" => match std::fs::read_to_string(&values[0]) {
+                Ok(text) => capture.devices_file = Some(SavedDevices::parse(&text)),
+                Err(err) => {
+                    eprintln!("Failed to read {}: {}", values[0], err);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            #[cfg(target_os = "linux")]
+            "--merge-similar" => {
+                capture.merge_similar = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--abs-threshold" => {
+                let Some(threshold) = values[0].parse().ok().filter(|&n: &i32| n >= 0) else {
+                    eprintln!("--abs-threshold requires a non-negative axis value difference");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.abs_threshold = Some(threshold);
+            }
+            #[cfg(target_os = "linux")]
+            "--events" => {
+                for kind in values[0].split(',') {
+                    match kind.trim() {
+                        "ff" => capture.count_ff = true,
+                        "led" => capture.count_led = true,
+                        other => {
+                            eprintln!("--events takes a comma-separated list of ff and led, not {}", other);
+                            std::process::exit(EXIT_USAGE);
+                        }
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            "--click-timing" => {
+                capture.click_timing = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--max-devices" => {
+                let Some(max) = values[0].parse().ok().filter(|&max: &usize| max > 0) else {
+                    eprintln!("--max-devices requires a positive device count");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.max_devices = Some(max);
+            }
+            #[cfg(target_os = "linux")]
+            "--speed-buckets" => {
+                capture.speed_buckets = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--per-slot" => {
+                capture.per_slot = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--per-axis" => {
+                capture.per_axis = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--debug-devices" => {
+                capture.debug_devices = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--dpi" => {
+                capture.dpi = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--auto-select" => {
+                capture.auto_select = true;
+            }
+            #[cfg(target_os = "linux")]
+            "--save-devices" => {
+                capture.save_devices = Some(std::path::PathBuf::from(&values[0]));
+            }
+            #[cfg(target_os = "linux")]
+            "--while-held" => {
+                let Some(key) = parse_key(&values[0]) else {
+                    eprintln!("--while-held requires a key or button name like BTN_SIDE or a numeric code");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.while_held = Some(key);
+            }
+            "--compare" => {
+                output.display = Display::Compare(Compare {
+                    names: [values[0].clone(), values[1].clone()],
+                });
+            }
+            "--top" => {
+                let Some(count) = values[0].parse().ok().filter(|&n| n > 0) else {
+                    eprintln!("--top requires a positive device count");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.display = Display::Top(count);
+            }
+            "--gauge" => {
+                output.display = Display::Gauge(values[0].clone());
+            }
+            "--device" => {
+                output.device = Some(values[0].clone());
+            }
+            "--probe" => {
+                output.probe = true;
+            }
+            "--duration" => {
+                let Some(secs) = values[0].parse().ok().filter(|&secs: &f64| secs > 0.0 && secs.is_finite()) else {
+                    eprintln!("--duration requires a positive number of seconds");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.duration = Some(Duration::from_secs_f64(secs));
+            }
+            "--timeout-idle" => {
+                let Some(secs) = values[0].parse().ok().filter(|&secs: &f64| secs > 0.0 && secs.is_finite()) else {
+                    eprintln!("--timeout-idle requires a positive number of seconds");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.idle_timeout = Some(Duration::from_secs_f64(secs));
+            }
+            "--runs" => {
+                let Some(runs) = values[0].parse().ok().filter(|&runs: &usize| runs > 0) else {
+                    eprintln!("--runs requires a positive number of runs");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.runs = runs;
+            }
+            "--min-samples" => {
+                let Some(count) = values[0].parse().ok() else {
+                    eprintln!("--min-samples requires a sample count");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.min_samples = count;
+            }
+            "--tail" => {
+                let Some(count) = values[0].parse().ok().filter(|&n| n > 0) else {
+                    eprintln!("--tail requires a positive sample count");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.tail = count;
+            }
+            "--format-hz" => {
+                let Some(format) = HzFormat::parse(&values[0]) else {
+                    eprintln!("--format-hz requires int or float");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.hz_format = format;
+            }
+            "--unit" => {
+                let Some(unit) = Unit::parse(&values[0]) else {
+                    eprintln!("--unit requires hz or khz");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.unit = unit;
+            }
+            "--trim" => {
+                let Some(trim) = Trim::parse(&values[0]) else {
+                    eprintln!("--trim requires none or iqr");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.trim = trim;
+            }
+            "--columns" => match Column::parse_list(&values[0]) {
+                Ok(columns) => output.columns = columns,
+                Err(name) => {
+                    let known: Vec<&str> = Column::NAMES.iter().map(|(name, _)| *name).collect();
+                    eprintln!("--columns: unknown field {:?}, expected some of {}", name, known.join(","));
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            "--round" => {
+                let Some(rounding) = Rounding::parse(&values[0]) else {
+                    eprintln!("--round requires floor, nearest or ceil");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.rounding = rounding;
+            }
+            "--allow-no-devices" => {
+                capture.allow_no_devices = true;
+            }
+            "--windowed" => {
+                output.windowed = true;
+            }
+            "--analyze" => {
+                output.analyze = true;
+            }
+            "--smoothing" => {
+                let Some(smoothing) = Smoothing::parse(&values[0]) else {
+                    eprintln!("--smoothing requires mean[:window], median[:window] or ewma[:alpha]");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.smoothing = smoothing;
+            }
+            "--window-ms" => {
+                let Some(ms) = values[0].parse::<u64>().ok().filter(|&ms| ms > 0) else {
+                    eprintln!("--window-ms requires a positive number of milliseconds");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.window_span = Some(Duration::from_millis(ms));
+            }
+            "--max-memory-mb" => {
+                let Some(mb) = values[0].parse::<usize>().ok().filter(|&mb| mb > 0) else {
+                    eprintln!("--max-memory-mb requires a positive number of megabytes");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.memory_budget = Some(mb.saturating_mul(1024 * 1024));
+            }
+            "--checkpoint" => {
+                let Some(secs) = values[0].parse::<f64>().ok().filter(|&secs| secs > 0.0 && secs.is_finite())
+                else {
+                    eprintln!("--checkpoint requires a positive number of seconds");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.checkpoint = Some(Duration::from_secs_f64(secs));
+            }
+            "--relative" => {
+                output.relative = true;
+            }
+            "--count-rate" => {
+                output.count_rate = true;
+            }
+            "--label" => {
+                output.label = Some(values[0].clone());
+            }
+            "--remote" => {
+                output.remote = true;
+            }
+            "--gaps" => {
+                output.gaps = true;
+            }
+            "--mode-rate" => {
+                output.mode_rate = true;
+            }
+            "--assert" => {
+                let Some(assertion) = RateAssertion::parse(&values[0]) else {
+                    eprintln!("--assert requires NAME=HZ±PCT%, e.g. Mouse=1000±5%");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.asserts.push(assertion);
+            }
+            "--min-hz" => {
+                let Some(hz) = values[0].parse().ok().filter(|&hz: &f64| hz > 0.0) else {
+                    eprintln!("--min-hz requires a positive rate in Hz");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.min_hz = Some(hz);
+            }
+            "--progress" => {
+                output.progress = true;
+            }
+            "--compare-previous" => {
+                output.compare_previous = true;
+            }
+            "--gnuplot" => match GnuplotSink::create(&values[0]) {
+                Ok(sink) => sinks.push(sink),
+                Err(err) => {
+                    eprintln!("--gnuplot: cannot create {}: {}", GnuplotSink::spool_path(&values[0]), err);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            "--csv" => match CsvSink::create(&values[0]) {
+                Ok(sink) => sinks.push(sink),
+                Err(err) => {
+                    eprintln!("--csv: cannot create {}: {}", values[0], err);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            "--split-output" => {
+                split_dir = Some(values[0].clone());
+            }
+            "--split-format" => {
+                split_format = match values[0].as_str() {
+                    "csv" => SplitFormat::Csv,
+                    "ndjson" => SplitFormat::Ndjson,
+                    _ => {
+                        eprintln!("--split-format requires csv or ndjson");
+                        std::process::exit(EXIT_USAGE);
+                    }
+                };
+            }
+            "--prometheus" => {
+                sinks.push(PrometheusSink { path: values[0].clone() });
+            }
+            #[cfg(unix)]
+            "--fifo" => match FifoSink::create(&values[0]) {
+                Ok(sink) => sinks.push(sink),
+                Err(err) => {
+                    eprintln!("--fifo: cannot create {}: {}", values[0], err);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            #[cfg(not(unix))]
+            "--fifo" => {
+                eprintln!("--fifo is not supported on Windows");
+                std::process::exit(EXIT_USAGE);
+            }
+            #[cfg(unix)]
+            "--syslog" => {
+                output.syslog = true;
+            }
+            #[cfg(not(unix))]
+            "--syslog" => {
+                eprintln!("--syslog is not supported on Windows");
+                std::process::exit(EXIT_USAGE);
+            }
+            "--flush-interval" => {
+                let Some(ms) = values[0].parse().ok().filter(|&ms: &u64| ms > 0) else {
+                    eprintln!("--flush-interval requires a positive number of milliseconds");
+                    std::process::exit(EXIT_USAGE);
+                };
+                output.batched = Some(BatchedStdout::spawn(Duration::from_millis(ms)));
+            }
+            "--system" => {
+                output.system = true;
+            }
+            "--no-summary" => {
+                output.no_summary = true;
+            }
+            "--json" => {
+                output.summary = SummaryFormat::Json;
+            }
+            "--json-pretty" => {
+                output.summary = SummaryFormat::JsonPretty;
+            }
+            "--capture" => match CaptureSink::create(&values[0]) {
+                Ok(sink) => sinks.push(sink),
+                Err(err) => {
+                    eprintln!("--capture: cannot create {}: {}", CaptureSink::spool_path(&values[0]), err);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            "--stdin" => {
+                capture.stdin = true;
+            }
+            "--replay" => {
+                capture.replay = Some(values[0].clone().into());
+            }
+            "--simulate" => {
+                let Some(rates) = parse_simulated_rates(&values[0]) else {
+                    eprintln!("--simulate requires a comma-separated list of rates from 1 to {}Hz", MAX_HZ - 1);
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.simulate = rates;
+            }
+            "--simulate-jitter" => {
+                let Some(jitter) = SimulatedJitter::parse(&values[0]) else {
+                    eprintln!("--simulate-jitter requires PCT, uniform:PCT or gaussian:PCT with PCT from 0 to 50");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.simulate_jitter = jitter;
+                simulate_options = true;
+            }
+            "--seed" => {
+                let Ok(seed) = values[0].parse() else {
+                    eprintln!("--seed requires a whole number");
+                    std::process::exit(EXIT_USAGE);
+                };
+                capture.seed = Some(seed);
+                simulate_options = true;
+            }
+            "--calibrate" => {
+                calibration = true;
+            }
+            "--subtract-overhead" => {
+                calibration = true;
+                subtract_overhead = true;
+            }
+            "--bench" => {
+                bench();
+                return;
+            }
+            "--color" => {
+                let Some(choice) = ColorChoice::parse(&values[0]) else {
+                    eprintln!("--color requires auto, always or never");
+                    std::process::exit(EXIT_USAGE);
+                };
+                color = choice;
+            }
+            "--banner" => {
+                banner = match values[0].as_str() {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => {
+                        eprintln!("--banner requires on or off");
+                        std::process::exit(EXIT_USAGE);
+                    }
+                };
+            }
+            _ => unreachable!("{} has no handler", spec.name),
+        }
+    }
+
+    output.ansi = color.enabled();
+    output.recorded = capture.recorded();
+    // Created once the format is known, whichever order the two came in
+    if let Some(dir) = split_dir {
+        match SplitSink::create(&dir, split_format) {
+            Ok(sink) => sinks.push(sink),
+            Err(err) => {
+                eprintln!("--split-output: cannot create {}: {}", dir, err);
+                std::process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    output.no_banner = !banner.unwrap_or(capture.duration.is_none());
+    if output.window_span.is_some()
+        && (matches!(output.smoothing, Smoothing::Ewma { .. }) || output.memory_budget.is_some())
+    {
+        eprintln!("--window-ms only applies to mean and median smoothing and can't be combined with --max-memory-mb");
+        std::process::exit(EXIT_USAGE);
+    }
+    #[cfg(target_os = "linux")]
+    if capture.focus_window.is_some() && capture.recorded() {
+        eprintln!("--focus-window only applies to live measurement, not --stdin or --replay");
+        std::process::exit(EXIT_USAGE);
+    }
+    if capture.idle_timeout.is_some() && capture.recorded() {
+        eprintln!("--timeout-idle only applies to live measurement, not --stdin or --replay");
+        std::process::exit(EXIT_USAGE);
+    }
+    if simulate_options && capture.simulate.is_empty() {
+        eprintln!("--simulate-jitter and --seed only apply to --simulate");
+        std::process::exit(EXIT_USAGE);
+    }
+    if subtract_overhead && capture.recorded() {
+        eprintln!("--subtract-overhead only applies to live measurement, not --stdin or --replay");
+        std::process::exit(EXIT_USAGE);
+    }
+    if [capture.stdin, capture.replay.is_some(), !capture.simulate.is_empty()].iter().filter(|&&set| set).count() > 1 {
+        eprintln!("--stdin, --replay and --simulate each supply the events; pick one");
+        std::process::exit(EXIT_USAGE);
+    }
+    if capture.runs > 1 && (capture.duration.is_none() || output.probe || capture.recorded()) {
+        eprintln!("--runs needs --duration and can't be combined with --probe, --stdin or --replay");
+        std::process::exit(EXIT_USAGE);
+    }
+    if output.probe && capture.duration.is_none() {
+        capture.duration = Some(PROBE_WINDOW);
+    }
+    output.color = color.colors();
+    if capture.recorded() && !matches!(output.display, Display::Scroll) {
+        eprintln!("--stdin and --replay can't be combined with --compare, --top or --gauge");
+        std::process::exit(EXIT_USAGE);
+    }
+    #[cfg(target_os = "linux")]
+    if capture.auto_select
+        && (output.system || output.device.is_some() || matches!(output.display, Display::Compare(_) | Display::Gauge(_)))
+    {
+        eprintln!("--auto-select picks one device itself and can't be combined with --system, --device, --compare or --gauge");
+        std::process::exit(EXIT_USAGE);
+    }
+    #[cfg(target_os = "linux")]
+    if (capture.speed_buckets || capture.per_slot || capture.per_axis) && output.system {
+        eprintln!("--speed-buckets, --per-slot and --per-axis measure devices separately and can't be combined with --system");
+        std::process::exit(EXIT_USAGE);
+    }
+    if output.system && matches!(output.display, Display::Compare(_)) {
+        eprintln!("--compare needs two devices and can't be combined with --system");
+        std::process::exit(EXIT_USAGE);
+    }
+    if output.remote {
+        if output.checkpoint.is_some() || !matches!(output.display, Display::Scroll) {
+            eprintln!("--remote sends its own per-second lines and can't be combined with --checkpoint or a live display");
+            std::process::exit(EXIT_USAGE);
+        }
+        output.checkpoint = Some(Duration::from_secs(1));
+        output.verbosity = Verbosity::Quiet;
+    }
+    // Keep stdout a single parseable document
+    if output.summary != SummaryFormat::Text || output.probe {
+        output.verbosity = Verbosity::Quiet;
+    }
+    #[cfg(unix)]
+    if output.syslog {
+        output.verbosity = Verbosity::Quiet;
+        open_syslog();
+    }
+    // Ahead of the file sinks, so stdout reports first
+    let stdout: Box<dyn OutputSink> = match output.summary {
+        _ if output.probe => Box::new(ProbeSink),
+        SummaryFormat::Text => Box::new(ConsoleSink),
+        SummaryFormat::Json => Box::new(JsonSink { indent: None }),
+        SummaryFormat::JsonPretty => Box::new(JsonSink { indent: Some(2) }),
+    };
+    sinks.0.insert(0, stdout);
+
+    if output.verbosity > Verbosity::Quiet && !output.no_banner {
+        match &output.label {
+            Some(label) => println!("Started {}, session {}", now_iso8601(), label),
+            None => println!("Started {}", now_iso8601()),
+        }
+        if capture.stdin {
+            println!("Reading events from stdin until end of input.\n");
+        } else if let Some(path) = &capture.replay {
+            println!("Replaying {} until its end.\n", path.display());
+        } else {
+            println!("Press CTRL-C to exit, p then Enter to pause/resume.\n");
+        }
+    }
+
+    if calibration {
+        let overhead = calibrate(&output);
+        let share = overhead.as_secs_f64() * 8000.0 * 100.0;
+        log_event(
+            Severity::Notice,
+            &format!(
+                "Calibration: {}ns of stats work per event, {:.2}% of a 125µs interval at 8000Hz{}",
+                overhead.as_nanos(),
+                share,
+                if subtract_overhead { "; subtracting it from every interval" } else { "" }
+            ),
+        );
+        if subtract_overhead {
+            output.overhead_ns = overhead.as_nanos() as u64;
+        }
+    }
+
+    let controls = Controls::new();
+    let (running, cancelled) = (controls.running.clone(), controls.cancelled.clone());
+
+    ctrlc::set_handler(move || {
+        cancelled.store(true, Ordering::SeqCst);
+        running.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+    #[cfg(unix)]
+    install_snapshot_handler();
+
+    // Stdin or the capture carries the events, and a quiet pipe isn't a hung read
+    if !capture.recorded() {
+        controls.spawn_pause_reader(output.verbosity);
+        controls.spawn_watchdog();
+    }
+    if let Some(timeout) = capture.idle_timeout {
+        // Idle from the start: a session that never sees input ends too
+        let last_event = Arc::new(AtomicU64::new(Timestamp::now().0));
+        output.last_event = Some(last_event.clone());
+        controls.stop_when_idle(timeout, last_event);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(class) = &capture.focus_window {
+        match x11::FocusQuery::open() {
+            Ok(query) => controls.spawn_focus_watch(query, class.clone(), output.verbosity),
+            Err(err) => {
+                eprintln!("--focus-window needs an X11 session: {}", err);
+                sinks.close(&output);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    let mut summaries = Vec::new();
+    let mut failure = None;
+    for run in 0..capture.runs.max(1) {
+        if run > 0 {
+            if controls.cancelled() {
+                break;
+            }
+            controls.restart();
+        }
+        if let Some(duration) = capture.duration {
+            controls.stop_after(duration);
+        }
+        let result = measure(&output, &capture, &controls, &mut sinks);
+        output.flush_events();
+        match result {
+            Ok(summary) => summaries.push(summary),
+            Err(err) => {
+                failure = Some(err);
+                break;
+            }
+        }
+    }
+    // A failed run still reports the ones before it and completes every file
+    let Some(mut summary) = summaries.pop() else {
+        sinks.close(&output);
+        failure.expect("a run either measured or failed").exit(&capture);
+    };
+    summary.earlier = summaries;
+    let cache = (output.compare_previous && !output.probe).then(PreviousRun::path).flatten();
+    if output.compare_previous && !output.probe && cache.is_none() {
+        eprintln!("No cache directory for --compare-previous; set XDG_CACHE_HOME");
+    }
+    summary.previous = cache.as_deref().map(PreviousRun::load);
+    sinks.summary(&output, &summary);
+    sinks.close(&output);
+    if let Some(err) = failure {
+        err.exit(&capture);
+    }
+    if output.probe {
+        if summary.probe_hz().is_none() {
+            std::process::exit(EXIT_NO_EVENTS);
+        }
+        return;
+    }
+    if let Some(cache) = &cache {
+        summary.next_run().save(cache);
+    }
+    // Every assertion reports, even after one failed
+    let failed = output.asserts.iter().filter(|assertion| !assertion.check(&output, &summary.devices)).count();
+    if failed > 0 {
+        std::process::exit(EXIT_ASSERT_FAILED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet() -> OutputConfig {
+        OutputConfig {
+            verbosity: Verbosity::Quiet,
+            ..OutputConfig::default()
+        }
+    }
+
+    /// Feeds events spaced `micros` apart, returning the stats afterwards.
+    fn feed(intervals: &[u64]) -> DeviceStats {
+        feed_with(&quiet(), intervals)
+    }
+
+    fn feed_with(output: &OutputConfig, intervals: &[u64]) -> DeviceStats {
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        let mut time = Timestamp::now();
+
+        stats.update(time, output, &mut sinks);
+        for &micros in intervals {
+            time += Duration::from_micros(micros);
+            stats.update(time, output, &mut sinks);
+        }
+        stats
+    }
+
+    #[test]
+    fn first_event_only_sets_reference() {
+        let stats = feed(&[]);
+        assert!(stats.hz_history.is_empty());
+        assert_eq!(stats.avg_hz, 0);
+        assert!(stats.prev_time.is_some());
+    }
+
+    #[test]
+    fn rejects_rate_at_cap() {
+        // 50µs is exactly MAX_HZ, which the filter excludes
+        let stats = feed(&[50]);
+        assert!(stats.hz_history.is_empty());
+    }
+
+    #[test]
+    fn accepts_fastest_rate_below_cap() {
+        // 51µs is the shortest whole-microsecond interval under the cap
+        let stats = feed(&[51]);
+        assert_eq!(stats.hz_history, [19607]);
+        assert_eq!(stats.avg_hz, 19607);
+    }
+
+    #[test]
+    fn zero_interval_is_skipped_but_moves_reference() {
+        let stats = feed(&[0, 1000]);
+        assert_eq!(stats.hz_history, [1000]);
+    }
+
+    #[test]
+    fn intervals_over_a_second_are_skipped() {
+        let stats = feed(&[1_000_001, 1000]);
+        assert_eq!(stats.hz_history, [1000]);
+        assert_eq!(stats.long_gaps, 1);
+    }
+
+    #[test]
+    fn jitter_averages_adjacent_interval_changes() {
+        // Changes of 200, 200 and 0µs
+        let stats = feed(&[1000, 1200, 1000, 1000]);
+        assert_eq!(stats.jitter.len(), 3);
+        assert!((stats.jitter_us().unwrap() - 400.0 / 3.0).abs() < 1e-9);
+
+        // A gap over a second doesn't count as a change
+        let stats = feed(&[1000, 1_000_001, 1200]);
+        assert_eq!(stats.jitter_us(), None);
+    }
+
+    #[test]
+    fn counts_rejected_intervals() {
+        // Zero, at the cap and over a second
+        let stats = feed(&[0, 50, 1_000_001]);
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.rejected, 3);
+    }
+
+    #[test]
+    fn counts_events_per_second() {
+        let output = OutputConfig {
+            count_rate: true,
+            ..quiet()
+        };
+        // 500 events in the first second, 250 in the next, then one closing it
+        let mut intervals = vec![2000; 499];
+        intervals.extend([4000; 250]);
+        intervals.push(1_000_000);
+        let stats = feed_with(&output, &intervals);
+        assert_eq!(stats.counted_hz, Some(250));
+        assert_eq!(stats.counted_average(), Some(375.0));
+    }
+
+    #[test]
+    fn backwards_timestamp_restarts_counting() {
+        let output = quiet();
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        stats.update(Timestamp(5_000_000), &output, &mut sinks);
+        stats.update(Timestamp(1_000_000), &output, &mut sinks);
+        stats.update(Timestamp(2_000_000), &output, &mut sinks);
+
+        assert_eq!(stats.backwards, 1);
+        assert_eq!(stats.hz_history, [1000]);
+    }
+
+    #[test]
+    fn window_evicts_oldest_sample() {
+        let mut intervals = vec![500; HZ_LIST];
+        intervals.push(1000);
+        let stats = feed(&intervals);
+
+        assert_eq!(stats.hz_history.len(), HZ_LIST);
+        assert_eq!(stats.hz_history.front(), Some(&2000));
+        assert_eq!(stats.hz_history.back(), Some(&1000));
+        assert_eq!(stats.hz_sum, 2000 * (HZ_LIST as u64 - 1) + 1000);
+        assert_eq!(stats.avg_hz, ((2000 * (HZ_LIST as u64 - 1) + 1000) / HZ_LIST as u64) as u32);
+    }
+
+    #[test]
+    fn time_window_evicts_old_samples() {
+        let output = OutputConfig {
+            window_span: Some(Duration::from_millis(10)),
+            ..quiet()
+        };
+        // 1ms apart, so a 10ms window holds ten samples whatever the count
+        let stats = feed_with(&output, &[1000; 20]);
+        assert_eq!(stats.hz_history.len(), 10);
+        assert_eq!(stats.hz_times.len(), 10);
+        assert_eq!(stats.avg_hz, 1000);
+        assert!(stats.first_avg_hz.is_some());
+
+        let stats = feed_with(&output, &[1000; 5]);
+        assert_eq!(stats.hz_history.len(), 5);
+        assert!(stats.first_avg_hz.is_none());
+    }
+
+    #[test]
+    fn warns_above_declared_rate() {
+        let mut stats = feed(&[500; 16]);
+        stats.declared_hz = Some(1000);
+        assert_eq!(stats.over_declared(), Some(1000));
+        stats.declared_hz = Some(2000);
+        assert_eq!(stats.over_declared(), None);
+    }
+
+    #[test]
+    fn device_colors_are_stable() {
+        assert_eq!(device_color("Logitech G Pro"), device_color("Logitech G Pro"));
+        let colors: std::collections::HashSet<u8> =
+            ["Mouse", "Keyboard", "Scroll", "Gamepad", "Tablet"].iter().map(|name| device_color(name)).collect();
+        assert!(colors.len() > 1);
+    }
+
+    #[test]
+    fn capture_records_read_back() {
+        let names = vec!["Mouse".to_string(), "Pad".to_string()];
+        let mut bytes = Vec::new();
+        write_capture_header(&mut bytes, Timestamp(5_000), &names).unwrap();
+        write_capture_record(&mut bytes, 0, 125_000).unwrap();
+        write_capture_record(&mut bytes, 1, 0).unwrap();
+        // Over u32::MAX nanoseconds, so it takes a long-delta record too
+        write_capture_record(&mut bytes, 0, 10_000_000_000).unwrap();
+        assert_eq!(bytes.len(), 8 + 2 + 8 + 2 + (2 + 5) + (2 + 3) + 6 * 4);
+
+        let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.names, names);
+        assert_eq!(reader.next_event().unwrap(), Some((Timestamp(130_000), 0)));
+        assert_eq!(reader.next_event().unwrap(), Some((Timestamp(5_000), 1)));
+        assert_eq!(reader.next_event().unwrap(), Some((Timestamp(10_000_130_000), 0)));
+        assert_eq!(reader.next_event().unwrap(), None);
+
+        let mut truncated = CaptureReader::new(&bytes[..bytes.len() - 3]).unwrap();
+        assert!(truncated.next_event().unwrap().is_some());
+        assert!(truncated.next_event().unwrap().is_some());
+        assert_eq!(truncated.next_event().unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(CaptureReader::new(&b"EVHZCAP\0\x02\0"[..]).is_err());
+    }
+
+    #[test]
+    fn sanitizes_split_file_names() {
+        assert_eq!(file_stem("Logitech G Pro"), "Logitech_G_Pro");
+        assert_eq!(file_stem("  SONiX USB Device / Keyboard "), "SONiX_USB_Device___Keyboard");
+        assert_eq!(file_stem(".."), "device");
+        assert_eq!(file_stem("Über-Maus"), "_ber-Maus");
+    }
+
+    #[test]
+    fn simulated_devices_keep_their_rate() {
+        assert_eq!(parse_simulated_rates("1000, 500"), Some(vec![1000, 500]));
+        assert_eq!(parse_simulated_rates("1000,0"), None);
+        assert_eq!(parse_simulated_rates("1000,"), None);
+        assert_eq!(parse_simulated_rates("20000"), None);
+
+        let jitter = SimulatedJitter::default();
+        let mut device = SimulatedDevice::new("Sim".to_string(), 8000, jitter, Timestamp(0), 1);
+        let intervals: Vec<u64> = (0..10_000).map(|_| device.interval_ns()).collect();
+        assert!(intervals.iter().all(|&ns| (122_500..=127_500).contains(&ns)));
+        let mean = intervals.iter().sum::<u64>() as f64 / intervals.len() as f64;
+        assert!((mean - 125_000.0).abs() < 250.0, "mean interval {}", mean);
+    }
+
+    #[test]
+    fn gaussian_jitter_is_seeded() {
+        assert_eq!(SimulatedJitter::parse("5"), Some(SimulatedJitter::Uniform(0.05)));
+        assert_eq!(SimulatedJitter::parse("gaussian:10%"), Some(SimulatedJitter::Gaussian(0.1)));
+        assert_eq!(SimulatedJitter::parse("60"), None);
+        assert_eq!(SimulatedJitter::parse("pink:5"), None);
+
+        let jitter = SimulatedJitter::Gaussian(0.1);
+        let run = |seed| {
+            let mut device = SimulatedDevice::new("Sim".to_string(), 1000, jitter, Timestamp(0), seed);
+            (0..20_000).map(|_| device.interval_ns() as f64).collect::<Vec<_>>()
+        };
+        let intervals = run(42);
+        assert_eq!(intervals, run(42));
+        assert_ne!(intervals, run(43));
+        let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+        let variance = intervals.iter().map(|ns| (ns - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+        assert!((mean - 1_000_000.0).abs() < 5_000.0, "mean interval {}", mean);
+        assert!((variance.sqrt() - 100_000.0).abs() < 5_000.0, "stddev {}", variance.sqrt());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_ratbagctl_output() {
+        let list = "warbling-mara:       Logitech G403 HERO Gaming Mouse\nNo devices here\n";
+        let devices = ratbag::parse_list(list);
+        assert_eq!(devices, vec![("warbling-mara".to_string(), "Logitech G403 HERO Gaming Mouse".to_string())]);
+        assert_eq!(ratbag::parse_dpi("1600dpi\n"), Some(1600));
+        assert_eq!(ratbag::parse_dpi("800x400dpi"), Some(800));
+        assert_eq!(ratbag::parse_dpi("dpi"), None);
+    }
+
+    #[test]
+    fn idle_timeout_stops_unless_paused() {
+        let controls = Controls::new();
+        let timeout = Duration::from_millis(50);
+        let ms = |ms: u64| Timestamp(ms * 1_000_000);
+        let last_event = AtomicU64::new(ms(1000).0);
+
+        controls.paused.store(true, Ordering::SeqCst);
+        assert_eq!(controls.idle_for(timeout, &last_event, ms(1200)), None);
+        assert_eq!(last_event.load(Ordering::Relaxed), ms(1200).0);
+
+        controls.paused.store(false, Ordering::SeqCst);
+        assert_eq!(controls.idle_for(timeout, &last_event, ms(1249)), None);
+        assert_eq!(controls.idle_for(timeout, &last_event, ms(1260)), Some(Duration::from_millis(60)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hidraw_permission_advice_points_at_udev() {
+        let hidraw = EvhzError::PermissionDenied("/dev").to_string();
+        assert!(hidraw.contains("/dev/hidraw*") && hidraw.contains("udev rule"), "{}", hidraw);
+        let input = EvhzError::PermissionDenied("/dev/input").to_string();
+        assert!(input.contains("usermod -aG input") && !input.contains("udev"), "{}", input);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn any_key_counts_keyboard_keys_only() {
+        use evdev::Key;
+        let any = KeyFilter::parse("ANY").unwrap();
+        assert!(any.matches(Key::KEY_A) && any.matches(Key::KEY_F24));
+        assert!(!any.matches(Key::BTN_LEFT) && !any.matches(Key::KEY_RESERVED));
+        assert_eq!(KeyFilter::parse("30"), Some(KeyFilter::One(Key::KEY_A)));
+        assert!(!KeyFilter::One(Key::KEY_A).matches(Key::KEY_B));
+    }
+
+    #[test]
+    fn relates_average_to_peak() {
+        assert_eq!(DeviceStats::new("Mouse".to_string()).peak_pct(), None);
+        let stats = feed(&[1000, 1000, 1000, 500, 1000]);
+        assert_eq!(stats.max_hz, 2000);
+        let pct = stats.peak_pct().unwrap();
+        assert!((pct - stats.avg_hz_exact / 20.0).abs() < 1e-9);
+        assert!(pct < 100.0);
+    }
+
+    #[test]
+    fn device_changes_are_tagged_records() {
+        let output = OutputConfig {
+            label: Some("bench".to_string()),
+            ..quiet()
+        };
+        let change = DeviceChange { added: false, index: 3, name: "Pad" };
+        let json = change.to_json(&output).to_string(None);
+        assert!(json.starts_with("{\"event\":\"device_removed\",\"time\":\""), "{}", json);
+        assert!(json.ends_with(",\"label\":\"bench\",\"name\":\"Pad\",\"index\":3}"), "{}", json);
+    }
+
+    #[test]
+    fn recorded_runs_last_as_long_as_their_timestamps() {
+        let output = quiet();
+        let mut sinks = Sinks::default();
+        let mut feed = EventFeed::new(&output);
+        for ms in [500, 1000, 3500] {
+            feed.push(&output, &mut sinks, Timestamp(ms * 1_000_000), "Mouse");
+        }
+        let summary = feed.finish(&output, Instant::now());
+        assert_eq!(summary.duration, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn parses_stdin_events() {
+        let (time, name) = parse_stdin_event("1500 Logitech G Pro ").unwrap();
+        assert_eq!((time, name), (Timestamp(1_500_000), "Logitech G Pro"));
+        assert!(parse_stdin_event("1500").is_none());
+        assert!(parse_stdin_event("1.5 Mouse").is_none());
+        assert!(parse_stdin_event("-3 Mouse").is_none());
+    }
+
+    #[test]
+    fn parses_column_lists() {
+        assert_eq!(Column::parse_list("avg,max,p99"), Ok(vec![Column::Avg, Column::Max, Column::P99]));
+        assert_eq!(Column::parse_list("Count, duration"), Ok(vec![Column::Count, Column::Duration]));
+        assert_eq!(Column::parse_list("avg,p95"), Err("p95".to_string()));
+
+        let stats = feed(&[1000, 1000, 1000, 500]);
+        assert_eq!(stats.percentile_hz(99), Some(2000));
+        assert_eq!(stats.percentile_hz(50), Some(1000));
+    }
+
+    #[test]
+    fn parses_rate_assertions() {
+        let expected = RateAssertion {
+            pattern: "G Pro".to_string(),
+            hz: 1000.0,
+            tolerance_pct: 5.0,
+        };
+        assert_eq!(RateAssertion::parse("G Pro=1000±5%"), Some(expected.clone()));
+        assert_eq!(RateAssertion::parse("G Pro = 1000 +- 5"), Some(expected));
+        assert_eq!(RateAssertion::parse("Mouse=1000"), None);
+        assert_eq!(RateAssertion::parse("=1000±5%"), None);
+    }
+
+    #[test]
+    fn previous_run_round_trips() {
+        let run = PreviousRun(vec![("Logitech\tG Pro".to_string(), 1002.5), ("Keyboard".to_string(), 125.0)]);
+        let parsed = PreviousRun::parse(&run.render());
+        assert_eq!(parsed.get("Logitech\tG Pro"), Some(1002.5));
+        assert_eq!(parsed.get("Keyboard"), Some(125.0));
+        assert_eq!(parsed.get("Mouse"), None);
+    }
+
+    #[test]
+    fn iqr_trim_drops_spikes() {
+        let mut intervals = vec![1000; 60];
+        intervals.extend([100, 100, 5000, 5000]);
+        let stats = feed(&intervals);
+
+        assert!(stats.avg_hz_exact > 1200.0);
+        assert_eq!(Trim::Iqr.average(&stats), 1000.0);
+        assert_eq!(Trim::None.average(&stats), stats.avg_hz_exact);
+    }
+
+    #[test]
+    fn summary_covers_whole_run() {
+        let mut intervals = vec![1000; HZ_LIST];
+        intervals.extend([500, 2000]);
+        let summary = DeviceSummary::new(feed(&intervals), &quiet());
+
+        assert_eq!(summary.count, HZ_LIST as u64 + 2);
+        assert_eq!((summary.min_hz, summary.max_hz), (500, 2000));
+        let variance = (1000.0f64.powi(2) + 500.0f64.powi(2)) / summary.count as f64;
+        assert!((summary.stddev_hz - variance.sqrt()).abs() < 1.0);
+    }
+
+    #[test]
+    fn summary_json_keeps_documented_key_order() {
+        let output = OutputConfig { gaps: true, ..quiet() };
+        let summary = DeviceSummary::new(feed_with(&output, &[1000; 8]), &output);
+        let Json::Object(fields) = summary.to_json(&output, None) else {
+            panic!("a device summary is an object");
+        };
+        let keys: Vec<&str> = fields.iter().map(|(key, _)| *key).collect();
+        assert_eq!(
+            keys,
+            ["name", "avg_hz", "samples", "count", "min_hz", "max_hz", "stddev_hz", "jitter_us", "trend_hz", "gaps"]
+        );
+    }
+
+    #[test]
+    fn trend_compares_latest_window_to_first() {
+        let mut intervals = vec![2000; HZ_LIST - 1];
+        assert_eq!(feed(&intervals).trend(), None);
+
+        intervals.push(2000);
+        intervals.extend([1000; HZ_LIST]);
+        assert_eq!(feed(&intervals).trend(), Some(('↑', 500)));
+
+        intervals.extend([2000; HZ_LIST]);
+        assert_eq!(feed(&intervals).trend(), Some(('→', 0)));
+
+        intervals.extend([2500; HZ_LIST]);
+        assert_eq!(feed(&intervals).trend(), Some(('↓', -100)));
+    }
+
+    #[test]
+    fn iso8601_formats_utc() {
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        // 2024-02-29 is a leap day
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(iso8601(time), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn detects_double_reporting() {
+        let output = OutputConfig { analyze: true, ..quiet() };
+
+        let doubled: Vec<u64> = [10, 990].repeat(HZ_LIST / 2);
+        let hz = feed_with(&output, &doubled).double_reporting_hz();
+        assert_eq!(hz, Some(1_000_000.0 / 990.0));
+
+        let steady = feed_with(&output, &[1000; HZ_LIST]).double_reporting_hz();
+        assert_eq!(steady, None);
+    }
+
+    fn parse(args: &[&str]) -> Result<Vec<(&'static str, Vec<String>)>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).map(|parsed| {
+            parsed
+                .into_iter()
+                .map(|(spec, values)| (spec.name, values))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn parses_values_inline_and_separate() {
+        assert_eq!(
+            parse(&["--top", "3", "--color=never", "--compare=a", "b"]),
+            Ok(vec![
+                ("--top", vec!["3".to_string()]),
+                ("--color", vec!["never".to_string()]),
+                ("--compare", vec!["a".to_string(), "b".to_string()]),
+            ])
+        );
+        assert_eq!(parse(&["--top"]), Err("--top requires N".to_string()));
+        assert!(parse(&["--quiet=yes"]).is_err());
+    }
+
+    #[test]
+    fn parses_clustered_short_flags() {
+        assert_eq!(
+            parse(&["-nq"]),
+            Ok(vec![("--nonverbose", vec![]), ("--quiet", vec![])])
+        );
+        assert!(parse(&["-nx"]).is_err());
+    }
+
+    #[test]
+    fn suggests_close_option() {
+        assert_eq!(
+            unknown_option("--gague"),
+            "Unknown option: --gague (did you mean --gauge?) See --help."
+        );
+        assert_eq!(unknown_option("--frobnicate"), "Unknown option: --frobnicate. See --help.");
+    }
+
+    #[test]
+    fn json_compact_and_pretty() {
+        let value = Json::Object(vec![
+            ("name", Json::String("a \"b\"\n".to_string())),
+            ("list", Json::Array(vec![Json::Number("1".to_string()), Json::Null])),
+            ("empty", Json::Array(vec![])),
+        ]);
+        assert_eq!(
+            value.to_string(None),
+            r#"{"name":"a \"b\"\n","list":[1,null],"empty":[]}"#
+        );
+        assert_eq!(
+            value.to_string(Some(2)),
+            "{\n  \"name\": \"a \\\"b\\\"\\n\",\n  \"list\": [\n    1,\n    null\n  ],\n  \"empty\": []\n}"
+        );
+    }
+
+    #[test]
+    fn parses_smoothing() {
+        assert_eq!(Smoothing::parse("mean"), Some(Smoothing::Mean { window: HZ_LIST }));
+        assert_eq!(Smoothing::parse("median:9"), Some(Smoothing::Median { window: 9 }));
+        assert_eq!(Smoothing::parse("ewma:0.5"), Some(Smoothing::Ewma { alpha: 0.5 }));
+        assert_eq!(Smoothing::parse("median:0"), None);
+        assert_eq!(Smoothing::parse("ewma:2"), None);
+        assert_eq!(Smoothing::parse("mode"), None);
+    }
+
+    #[test]
+    fn median_ignores_outlier() {
+        let output = OutputConfig {
+            smoothing: Smoothing::Median { window: 5 },
+            ..quiet()
+        };
+        let stats = feed_with(&output, &[1000, 1000, 100, 1000, 1000]);
+        assert_eq!(stats.hz_history.len(), 5);
+        assert_eq!(stats.avg_hz, 1000);
+    }
+
+    #[test]
+    fn median_follows_window_larger_than_default() {
+        let window = HZ_LIST * 3;
+        let output = OutputConfig {
+            smoothing: Smoothing::Median { window },
+            ..quiet()
+        };
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        let mut time = Timestamp(0);
+        stats.update(time, &output, &mut sinks);
+        // Repeats and runs of equal rates, past the window so it evicts
+        for idx in 0..window * 3 {
+            time += Duration::from_micros(500 + (idx * 37 % 11) as u64 * 50);
+            stats.update(time, &output, &mut sinks);
+
+            let mut sorted: Vec<u32> = stats.hz_history.iter().copied().collect();
+            sorted.sort_unstable();
+            let mid = sorted.len() / 2;
+            let expected = if sorted.len().is_multiple_of(2) {
+                (sorted[mid - 1] as f64 + sorted[mid] as f64) / 2.0
+            } else {
+                sorted[mid] as f64
+            };
+            assert_eq!(stats.smoothed, expected, "after {} samples", idx + 1);
+        }
+        assert_eq!(stats.hz_history.len(), window);
+    }
+
+    #[test]
+    fn ewma_weights_latest_sample() {
+        let output = OutputConfig {
+            smoothing: Smoothing::Ewma { alpha: 0.5 },
+            ..quiet()
+        };
+        // 1000Hz, then 500Hz: 0.5 * 500 + 0.5 * 1000
+        let stats = feed_with(&output, &[1000, 2000]);
+        assert_eq!(stats.avg_hz, 750);
+    }
+
+    #[test]
+    fn memory_cap_shrinks_window() {
+        let output = OutputConfig {
+            smoothing: Smoothing::Median { window: 1_000_000 },
+            memory_budget: Some(1024 * 1024),
+            ..quiet()
+        };
+        // 1 MiB over 4 devices leaves 256 KiB each, 24 bytes per median sample
+        assert_eq!(output.fit_memory(4).smoothing, Smoothing::Median { window: 10_922 });
+
+        let small = OutputConfig { memory_budget: Some(1024 * 1024), ..quiet() };
+        assert_eq!(small.fit_memory(4).smoothing, Smoothing::default());
+    }
+
+    #[test]
+    fn gap_buckets_split_at_bounds() {
+        let output = OutputConfig { gaps: true, ..quiet() };
+        let stats = feed_with(&output, &[0, 199, 200, 499, 500, 999, 1000, 5000]);
+        assert_eq!(stats.gap_counts, [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn measures_sub_microsecond_intervals() {
+        let output = quiet();
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        let mut time = Timestamp(0);
+        stats.update(time, &output, &mut sinks);
+        // 999.5µs would truncate to 999µs (1001Hz) at microsecond precision
+        time += Duration::from_nanos(999_500);
+        stats.update(time, &output, &mut sinks);
+        assert_eq!(stats.avg_hz, 1000);
+    }
+
+    #[test]
+    fn sub_microsecond_interval_hits_cap_at_full_rate() {
+        let output = OutputConfig { analyze: true, ..quiet() };
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        let mut time = Timestamp(0);
+        stats.update(time, &output, &mut sinks);
+        // 0.5µs is 2MHz: kept as a raw interval, rejected as a sample
+        time += Duration::from_nanos(500);
+        stats.update(time, &output, &mut sinks);
+        assert_eq!(stats.intervals, [500]);
+        assert!(stats.hz_history.is_empty());
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let output = |rounding| OutputConfig { rounding, ..quiet() };
+        assert_eq!(output(Rounding::Floor).format_hz(999.5), "999");
+        assert_eq!(output(Rounding::Nearest).format_hz(999.5), "1000");
+        assert_eq!(output(Rounding::Ceil).format_hz(999.1), "1000");
+
+        let float = OutputConfig { hz_format: HzFormat::Float, ..output(Rounding::Floor) };
+        assert_eq!(float.format_hz(999.96), "1000.0");
+    }
+
+    #[test]
+    fn signed_deltas_show_plus_zero() {
+        let output = OutputConfig { rounding: Rounding::Nearest, ..quiet() };
+        assert_eq!(output.signed_hz(15.0), "+15");
+        assert_eq!(output.signed_hz(-15.0), "-15");
+        assert_eq!(output.signed_hz(0.0), "+0");
+        assert_eq!(output.signed_hz(-0.3), "+0");
+        let khz = OutputConfig { unit: Unit::KHz, ..quiet() };
+        assert_eq!(khz.signed_hz(-20.0), "+0.0");
+        assert_eq!(khz.signed_hz(-200.0), "-0.2");
+    }
+
+    #[test]
+    fn windowed_ring_keeps_last_second() {
+        let output = OutputConfig { windowed: true, ..quiet() };
+        // 1500 events 1ms apart: only the last second's worth stays
+        let stats = feed_with(&output, &[1000; 1499]);
+        assert_eq!(stats.recent.len(), 1000);
+    }
+
+    #[test]
+    fn windowed_rate_of_recorded_input_follows_its_timestamps() {
+        let output = OutputConfig {
+            windowed: true,
+            recorded: true,
+            ..quiet()
+        };
+        // Two seconds of 1ms intervals recorded long before this run
+        let mut stats = DeviceStats::new("Test".to_string());
+        let mut sinks = Sinks::default();
+        for ms in 0..2000 {
+            stats.update(Timestamp(1_000_000_000 + ms * 1_000_000), &output, &mut sinks);
+        }
+        assert_eq!(stats.live_hz(&output), 1000.0);
+        assert_eq!(stats.live_hz(&OutputConfig { recorded: false, ..output }), 0.0);
+    }
+
+    #[test]
+    fn profile_expands_before_explicit_flags() {
+        let args: Vec<String> = ["--smoothing", "median:8", "--profile", "gaming-mouse"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let parsed = parse_args(&args).and_then(expand_profiles).unwrap();
+        let names: Vec<&str> = parsed.iter().map(|(spec, _)| spec.name).collect();
+        let last = names.len() - 1;
+        assert_eq!(names.first(), Some(&"--smoothing"));
+        assert!(names.contains(&"--min-hz"));
+        assert_eq!(names.contains(&"--per-axis"), cfg!(target_os = "linux"));
+        assert_eq!((names[last], parsed[last].1.as_slice()), ("--smoothing", ["median:8".to_string()].as_slice()));
+
+        let args = vec!["--profile".to_string(), "nope".to_string()];
+        assert!(parse_args(&args).and_then(expand_profiles).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn saved_devices_match_by_name_then_path() {
+        let saved = SavedDevices::parse("/dev/input/event3\tG Pro\n/dev/input/event7\tKeyboard\n");
+        assert_eq!(SavedDevices::parse(&saved.render()).0, saved.0);
+
+        // Renumbered mouse is still found by name
+        assert!(saved.contains("/dev/input/event5", "G Pro", &["G Pro", "Other"]));
+        // Keyboard's name is gone, so its old path is used
+        assert!(saved.contains("/dev/input/event7", "Keyboard 2", &["G Pro", "Keyboard 2"]));
+        assert!(!saved.contains("/dev/input/event3", "Other", &["G Pro", "Other"]));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn click_timing_pairs_presses_and_releases() {
+        let mut timing = ClickTiming::default();
+        timing.release(272, Timestamp(0));
+        timing.press(272, Timestamp(1_000_000));
+        timing.press(273, Timestamp(2_000_000));
+        timing.release(272, Timestamp(81_000_000));
+        timing.release(273, Timestamp(42_000_000));
+
+        assert_eq!(timing.unmatched, 1);
+        assert_eq!(timing.holds, 2);
+        assert_eq!((timing.min_nanos, timing.max_nanos), (40_000_000, 80_000_000));
+        assert_eq!(timing.total_nanos / timing.holds, 60_000_000);
+    }
+
+    #[test]
+    fn khz_unit_uses_one_decimal() {
+        let output = OutputConfig { unit: Unit::KHz, ..quiet() };
+        assert_eq!(output.format_hz(7987.0), "8.0");
+        assert_eq!(output.padded_hz(999.5), "  1.0");
+        assert_eq!(output.unit.suffix(), "kHz");
+    }
+
+    #[test]
+    fn dominant_rate_of_bimodal_device() {
+        // Mostly ~1000Hz with jitter, a quarter of the samples at 500Hz
+        let mut intervals = vec![2000; HZ_LIST / 4];
+        intervals.extend([990, 1000, 1010].repeat(HZ_LIST / 4));
+        let stats = feed(&intervals);
+        let (hz, share) = stats.dominant_hz().unwrap();
+        assert_eq!(hz, 1000);
+        assert_eq!(share, 0.75);
+
+        // Off any standard rate: nearest 10Hz
+        assert_eq!(feed(&[16_000; 4]).dominant_hz().map(|(hz, _)| hz), Some(60));
+    }
+
+    #[test]
+    fn tail_keeps_latest_raw_intervals() {
+        let output = OutputConfig { tail: 3, ..quiet() };
+        // The zero interval is rejected as a sample but still shown
+        let stats = feed_with(&output, &[1000, 2000, 0, 4000]);
+        assert_eq!(stats.tail, [2_000_000, 0, 4_000_000]);
+    }
+
+    #[test]
+    fn min_samples_holds_average_at_zero() {
+        let output = OutputConfig { min_samples: 4, ..quiet() };
+        let stats = feed_with(&output, &[1000; 3]);
+        assert_eq!(stats.avg_hz, 0);
+        let stats = feed_with(&output, &[1000; 4]);
+        assert_eq!(stats.avg_hz, 1000);
+
+        // Never past a full window
+        let output = OutputConfig { min_samples: HZ_LIST * 2, ..quiet() };
+        assert_eq!(feed_with(&output, &[1000; HZ_LIST]).avg_hz, 1000);
+    }
+
+    #[test]
+    fn clip_counts_characters() {
+        assert_eq!(clip("Mouse: 1000Hz", 6), "Mouse:");
+        assert_eq!(clip("Δ +5Hz", 3), "Δ +");
+        assert_eq!(clip("short", 80), "short");
+    }
+
+    #[test]
+    fn unmatched_gauge_is_a_not_found_error() {
+        let err = Display::Gauge("Nope".to_string()).select(&["Mouse"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_NO_DEVICES);
+        assert!(!err.unavailable());
+        assert_eq!(err.to_string(), "Could not find a device matching 'Nope'");
+    }
 }
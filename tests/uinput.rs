@@ -0,0 +1,81 @@
+//! End-to-end check of the Linux evdev backend against a virtual mouse.
+//!
+//! Needs write access to `/dev/uinput` and read access to `/dev/input`, so
+//! it is ignored by default. Run it as root with
+//! `cargo test --test uinput -- --ignored`.
+#![cfg(target_os = "linux")]
+
+use evdev::uinput::VirtualDeviceBuilder;
+use evdev::{AttributeSet, EventType, InputEvent, Key, RelativeAxisType};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const DEVICE_NAME: &str = "evhz-test-mouse";
+const RATE_HZ: u64 = 500;
+
+#[test]
+#[ignore = "needs /dev/uinput and /dev/input access"]
+fn measures_virtual_mouse_rate() {
+    let mut keys = AttributeSet::<Key>::new();
+    keys.insert(Key::BTN_LEFT);
+    let mut axes = AttributeSet::<RelativeAxisType>::new();
+    axes.insert(RelativeAxisType::REL_X);
+    axes.insert(RelativeAxisType::REL_Y);
+
+    let mut mouse = VirtualDeviceBuilder::new()
+        .expect("open /dev/uinput")
+        .name(DEVICE_NAME)
+        .with_keys(&keys)
+        .unwrap()
+        .with_relative_axes(&axes)
+        .unwrap()
+        .build()
+        .expect("create virtual mouse");
+    // Give udev time to create the event node before evhz scans
+    std::thread::sleep(Duration::from_millis(500));
+
+    let child = Command::new(env!("CARGO_BIN_EXE_evhz"))
+        .arg("--json")
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("start evhz");
+    std::thread::sleep(Duration::from_secs(1));
+
+    // Busy-wait on deadlines rather than sleeping so the cadence stays exact
+    let period = Duration::from_nanos(1_000_000_000 / RATE_HZ);
+    let mut deadline = Instant::now();
+    for _ in 0..RATE_HZ * 2 {
+        deadline += period;
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        let motion = [
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, 1),
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, 1),
+        ];
+        mouse.emit(&motion).unwrap();
+    }
+
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let output = child.wait_with_output().expect("wait for evhz");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let key = format!("\"name\":\"{}\",\"avg_hz\":", DEVICE_NAME);
+    let start = stdout.find(&key).unwrap_or_else(|| panic!("device missing in {}", stdout)) + key.len();
+    let avg_hz: f64 = stdout[start..]
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()
+        .and_then(|number| number.parse().ok())
+        .expect("avg_hz value");
+
+    let tolerance = RATE_HZ as f64 * 0.1;
+    assert!(
+        (avg_hz - RATE_HZ as f64).abs() <= tolerance,
+        "measured {}Hz, expected {}Hz ± {}",
+        avg_hz,
+        RATE_HZ,
+        tolerance
+    );
+}